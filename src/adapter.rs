@@ -1,17 +1,108 @@
-use rusb::{DeviceHandle, GlobalContext};
+use crate::{
+    config::{DeadzoneShape, GcButton, MergeMode, SendInitCommand},
+    debug::M64Message,
+};
+use rusb::{Device, DeviceHandle, GlobalContext};
 use std::{
     convert::{TryFrom, TryInto},
     fmt::Debug,
-    thread,
-    time::Duration,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
+mod mock;
+
+pub use mock::MockAdapter;
+
 const ENDPOINT_IN: u8 = 0x81;
 const ENDPOINT_OUT: u8 = 0x02;
 const READ_LEN: usize = 37;
 
-pub struct GcAdapter {
+/// The first byte of every input report.
+const REPORT_HEADER: u8 = 0x21;
+
+/// How many malformed reports in a row are discarded before the adapter is reconnected.
+const MAX_MALFORMED_REPORTS: u32 = 10;
+
+/// The USB vendor and product IDs of the known adapters. The official adapter and the clones in Wii U mode (e.g.
+/// Mayflash) share the Nintendo IDs.
+const KNOWN_ADAPTERS: [(u16, u16); 1] = [(0x057E, 0x0337)];
+
+/// The product string of the official adapter.
+const OFFICIAL_PRODUCT: &str = "WUP-028";
+
+/// How long a newly opened adapter has to send its first input with `SendInitCommand::Auto`. Adapters send a report
+/// about every 8 ms, also without controllers.
+const FIRST_INPUT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The kind of adapter that is connected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AdapterVariant {
+    /// The official Nintendo adapter.
+    Official,
+    /// A third-party clone, which has the same USB IDs but a different product string.
+    Clone,
+}
+
+/// Something that input reports can be read from, like a connected adapter.
+pub trait AdapterSource: Send {
+    /// Wait for new input, for at most `timeout`. Returns `None` if no input was received in time.
+    fn read_blocking(&self, timeout: Duration) -> rusb::Result<Option<[u8; READ_LEN]>>;
+
+    /// Get a `RumbleWriter` for the adapter, if it can rumble.
+    fn rumble_writer(&self) -> Option<RumbleWriter>;
+
+    /// Get the bus number and address of the USB device, if this is a USB device.
+    fn usb_address(&self) -> Option<(u8, u8)>;
+
+    /// Get a name that identifies the adapter between runs, for saving its calibration.
+    fn id(&self) -> &str;
+}
+
+/// A device handle with the input interface of the adapter claimed. When the handle is dropped, the rumble is stopped,
+/// the interface is released, and the kernel driver is attached again if it was detached to claim it. The handle is
+/// shared by the adapter and its `RumbleWriter`s, so this happens when the last of them is dropped.
+struct ClaimedHandle {
     handle: DeviceHandle<GlobalContext>,
+    interface: u8,
+    /// Whether the kernel driver was detached from the interface, which only happens on Linux.
+    detached_kernel_driver: bool,
+}
+
+impl Deref for ClaimedHandle {
+    type Target = DeviceHandle<GlobalContext>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl Drop for ClaimedHandle {
+    fn drop(&mut self) {
+        // The adapter keeps the motors running until it is told otherwise, even without a program using it
+        let _ = self.handle.write_interrupt(
+            ENDPOINT_OUT,
+            &RumbleWriter::report([false; 4]),
+            Duration::from_millis(16),
+        );
+        let _ = self.handle.release_interface(self.interface);
+        if self.detached_kernel_driver {
+            let _ = self.handle.attach_kernel_driver(self.interface);
+        }
+    }
+}
+
+pub struct GcAdapter {
+    handle: Arc<ClaimedHandle>,
+    variant: AdapterVariant,
+    /// The serial number of the adapter, or its USB port path if it has none.
+    id: String,
+    /// The number of malformed reports that were read in a row.
+    malformed_reports: AtomicU32,
 }
 
 impl Debug for GcAdapter {
@@ -27,64 +118,360 @@ impl Debug for GcAdapter {
 }
 
 impl GcAdapter {
+    /// Connect to the first adapter that is found.
     pub fn new() -> Result<Self, rusb::Error> {
         let device = rusb::devices()?
             .iter()
-            .find(|dev| {
-                let dev_desc = dev.device_descriptor().unwrap();
-                dev_desc.vendor_id() == 0x057E && dev_desc.product_id() == 0x0337
-            })
+            .find(is_gc_adapter)
             .ok_or(rusb::Error::NoDevice)?;
 
-        let mut handle = device.open()?;
+        GcAdapter::open_with_init(device, SendInitCommand::Auto)
+    }
+
+    /// Connect to all adapters that are found.
+    pub fn open_all() -> Vec<Self> {
+        GcAdapter::open_new(&[], SendInitCommand::Auto)
+    }
+
+    /// Connect to all adapters that are found, except the ones in `connected`.
+    pub fn open_new(connected: &[Box<dyn AdapterSource>], init: SendInitCommand) -> Vec<Self> {
+        let devices = match rusb::devices() {
+            Ok(d) => d,
+            Err(_) => return Vec::new(),
+        };
+
+        devices
+            .iter()
+            .filter(is_gc_adapter)
+            .filter(|dev| {
+                let address = Some((dev.bus_number(), dev.address()));
+                !connected
+                    .iter()
+                    .any(|source| source.usb_address() == address)
+            })
+            .filter_map(|dev| GcAdapter::open_with_init(dev, init).ok())
+            .collect()
+    }
 
-        if handle.kernel_driver_active(0).unwrap_or(false) {
-            handle.detach_kernel_driver(0)?;
+    /// Connect to the adapter. With `SendInitCommand::Auto`, the init command is sent, and if the adapter then sends no
+    /// input, it is opened again without the init command, for clones that stop working after it.
+    fn open_with_init(
+        device: Device<GlobalContext>,
+        init: SendInitCommand,
+    ) -> Result<Self, rusb::Error> {
+        if init != SendInitCommand::Auto {
+            return GcAdapter::open(device, init);
+        }
+
+        let gc = GcAdapter::open(device.clone(), SendInitCommand::Auto)?;
+        if let Ok(Some(_)) = gc.read_blocking(FIRST_INPUT_TIMEOUT) {
+            return Ok(gc);
+        }
+        // The interface has to be released before the adapter can be opened again
+        drop(gc);
+
+        debug_print!(
+            M64Message::Info,
+            "Adapter sent no input after the init command, opening it again without it"
+        );
+        let gc = GcAdapter::open(device, SendInitCommand::No)?;
+        match gc.read_blocking(FIRST_INPUT_TIMEOUT)? {
+            Some(_) => Ok(gc),
+            None => {
+                debug_print!(
+                    M64Message::Warning,
+                    "Adapter sent no input with or without the init command, ignoring it"
+                );
+                Err(rusb::Error::Timeout)
+            }
+        }
+    }
+
+    fn open(device: Device<GlobalContext>, init: SendInitCommand) -> Result<Self, rusb::Error> {
+        let handle = device.open()?;
+        let (interface, setting) = input_interface(&device);
+
+        // On Linux, the kernel HID driver can be bound to the adapter, which keeps the interface from being claimed
+        let detached_kernel_driver = handle.kernel_driver_active(interface).unwrap_or(false);
+        if detached_kernel_driver {
+            handle.detach_kernel_driver(interface)?;
         }
 
         // From Dolphin emulator source:
         // "This call makes Nyko-brand (and perhaps other) adapters work.
         // However it returns LIBUSB_ERROR_PIPE with Mayflash adapters."
-        let _ = handle.write_control(0x21, 11, 0x0001, 0, &[], Duration::from_millis(1000));
+        let _ = handle.write_control(
+            0x21,
+            11,
+            0x0001,
+            interface as u16,
+            &[],
+            Duration::from_millis(1000),
+        );
 
-        handle.claim_interface(0)?;
-        handle.write_interrupt(ENDPOINT_OUT, &[0x13], Duration::from_millis(16))?;
-
-        Ok(GcAdapter { handle })
-    }
-
-    /// Continuously try to connect to the adapter
-    pub fn blocking_connect() -> Self {
-        loop {
-            if let Ok(gc) = GcAdapter::new() {
-                break gc;
+        if let Err(e) = handle.claim_interface(interface) {
+            if detached_kernel_driver {
+                let _ = handle.attach_kernel_driver(interface);
             }
+            return Err(e);
+        }
+        // From here on, the interface is released (and the kernel driver attached again) when the handle is dropped
+        let handle = ClaimedHandle {
+            handle,
+            interface,
+            detached_kernel_driver,
+        };
+        if setting != 0 {
+            handle.set_alternate_setting(interface, setting)?;
+        }
 
-            thread::park_timeout(Duration::from_secs(1));
+        // Most adapters only send input after the init command, but some clones refuse it
+        let init_result = match init {
+            SendInitCommand::No => None,
+            SendInitCommand::Yes | SendInitCommand::Auto => {
+                Some(handle.write_interrupt(ENDPOINT_OUT, &[0x13], Duration::from_millis(16)))
+            }
+        };
+        match (init, init_result) {
+            (_, None) => debug_print!(M64Message::Info, "Not sending init command"),
+            (_, Some(Ok(_))) => debug_print!(M64Message::Info, "Sent init command"),
+            (SendInitCommand::Auto, Some(Err(e))) => debug_print!(
+                M64Message::Info,
+                "Adapter did not accept init command ({:?}), continuing without it",
+                e
+            ),
+            (_, Some(Err(e))) => return Err(e),
         }
+
+        // Logged so that the adapter can be identified from the log, since clones share the USB IDs
+        let desc = device.device_descriptor()?;
+        let product = handle.read_product_string_ascii(&desc).unwrap_or_default();
+        let manufacturer = handle
+            .read_manufacturer_string_ascii(&desc)
+            .unwrap_or_default();
+        let variant = if product == OFFICIAL_PRODUCT {
+            AdapterVariant::Official
+        } else {
+            AdapterVariant::Clone
+        };
+        debug_print!(
+            M64Message::Info,
+            "Found {:?} adapter {:04X}:{:04X} version {} on bus {} address {} (manufacturer: {:?}, product: {:?})",
+            variant,
+            desc.vendor_id(),
+            desc.product_id(),
+            desc.device_version(),
+            device.bus_number(),
+            device.address(),
+            manufacturer,
+            product
+        );
+
+        // Most adapters have no serial number, but are usually plugged into the same USB port
+        let id = handle
+            .read_serial_number_string_ascii(&desc)
+            .ok()
+            .filter(|serial| !serial.is_empty())
+            .unwrap_or_else(|| {
+                let ports = device.port_numbers().unwrap_or_default();
+                let ports: Vec<_> = ports.iter().map(u8::to_string).collect();
+                format!("usb-{}-{}", device.bus_number(), ports.join("."))
+            });
+        debug_print!(M64Message::Info, "Adapter ID: {}", id);
+
+        Ok(GcAdapter {
+            handle: Arc::new(handle),
+            variant,
+            id,
+            malformed_reports: AtomicU32::new(0),
+        })
+    }
+
+    /// Get the kind of adapter, for differences in how it is used.
+    pub fn variant(&self) -> AdapterVariant {
+        self.variant
     }
 
     pub fn read(&self) -> rusb::Result<[u8; READ_LEN]> {
+        self.read_blocking(Duration::from_millis(16))
+            .map(|buf| buf.unwrap_or([0; READ_LEN]))
+    }
+
+    /// Wait for the adapter to send new input, for at most `timeout`. Returns `None` if no input was received in
+    /// time, or if the report was malformed (e.g. a short transfer after a USB hiccup), so that the previous state is
+    /// kept. After `MAX_MALFORMED_REPORTS` malformed reports in a row, this returns an error so that the adapter is
+    /// reconnected.
+    pub fn read_blocking(&self, timeout: Duration) -> rusb::Result<Option<[u8; READ_LEN]>> {
         let mut buf = [0; READ_LEN];
 
-        match self
-            .handle
-            .read_interrupt(ENDPOINT_IN, &mut buf, Duration::from_millis(16))
-        {
-            Ok(_) | Err(rusb::Error::Timeout) => Ok(buf),
-            Err(e) => Err(e),
+        let len = match self.handle.read_interrupt(ENDPOINT_IN, &mut buf, timeout) {
+            Ok(len) => len,
+            Err(rusb::Error::Timeout) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        if is_valid_report(&buf[..len]) {
+            self.malformed_reports.store(0, Ordering::Relaxed);
+            return Ok(Some(buf));
+        }
+
+        let malformed = self.malformed_reports.fetch_add(1, Ordering::Relaxed) + 1;
+        debug_print!(
+            M64Message::Verbose,
+            "Discarded malformed report from adapter ({} bytes, header {:#04X})",
+            len,
+            buf[0]
+        );
+        if malformed >= MAX_MALFORMED_REPORTS {
+            self.malformed_reports.store(0, Ordering::Relaxed);
+            return Err(rusb::Error::Io);
+        }
+
+        Ok(None)
+    }
+
+    /// Get a `RumbleWriter` for this adapter, which can be used from another thread.
+    pub fn rumble_writer(&self) -> RumbleWriter {
+        RumbleWriter {
+            handle: Arc::clone(&self.handle),
         }
     }
 }
 
-#[derive(Debug)]
+impl AdapterSource for GcAdapter {
+    fn read_blocking(&self, timeout: Duration) -> rusb::Result<Option<[u8; READ_LEN]>> {
+        GcAdapter::read_blocking(self, timeout)
+    }
+
+    fn rumble_writer(&self) -> Option<RumbleWriter> {
+        Some(GcAdapter::rumble_writer(self))
+    }
+
+    fn usb_address(&self) -> Option<(u8, u8)> {
+        let device = self.handle.device();
+        Some((device.bus_number(), device.address()))
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Writes rumble output reports to an adapter.
+#[derive(Clone)]
+pub struct RumbleWriter {
+    handle: Arc<ClaimedHandle>,
+}
+
+impl RumbleWriter {
+    /// Turn the rumble motor on or off for all channels with a single output report.
+    pub fn set_rumble(&self, rumble: [bool; 4]) -> rusb::Result<()> {
+        self.handle
+            .write_interrupt(
+                ENDPOINT_OUT,
+                &Self::report(rumble),
+                Duration::from_millis(16),
+            )
+            .map(|_| ())
+    }
+
+    /// Get the output report that sets the rumble of all channels.
+    fn report(rumble: [bool; 4]) -> [u8; 5] {
+        let [r1, r2, r3, r4] = rumble.map(u8::from);
+        [0x11, r1, r2, r3, r4]
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct AdapterState {
     pub buf: [u8; READ_LEN],
+    /// The stick calibration for each channel.
+    pub calibration: [Calibration; 4],
+    /// The stick values of the first read after each controller connected.
+    origins: [[u8; 4]; 4],
+    /// The calibration of each channel that was saved in an earlier run, which is used instead of the origin.
+    saved: [Option<[i8; 4]>; 4],
 }
 
 impl AdapterState {
     pub const fn new() -> Self {
-        AdapterState { buf: [0; READ_LEN] }
+        AdapterState {
+            buf: [0; READ_LEN],
+            calibration: [Calibration::new(); 4],
+            origins: [[128; 4]; 4],
+            saved: [None; 4],
+        }
+    }
+
+    /// Create a state that uses the given saved offsets for each channel when a controller connects.
+    pub const fn with_saved(saved: [Option<[i8; 4]>; 4]) -> Self {
+        AdapterState {
+            saved,
+            ..AdapterState::new()
+        }
+    }
+
+    /// Update the state with a new read from the adapter. The stick values of newly connected controllers are
+    /// recorded as their origin, which is used as the neutral position if `use_origin` is set and the channel has no
+    /// saved calibration. If `calibrate_on_connect` is set, the sticks of newly connected controllers are also
+    /// calibrated. Returns the channels whose calibration finished with this read.
+    pub fn update(
+        &mut self,
+        buf: [u8; READ_LEN],
+        use_origin: bool,
+        calibrate_on_connect: bool,
+    ) -> [bool; 4] {
+        let was_connected = [0, 1, 2, 3].map(|i| self.is_connected(i));
+        let mut calibrated = [false; 4];
+        self.buf = buf;
+
+        for (i, &was_connected) in was_connected.iter().enumerate() {
+            if !self.is_connected(i) {
+                continue;
+            }
+
+            let raw = self.raw_sticks(i);
+            if !was_connected {
+                self.origins[i] = raw;
+                if let Some(offsets) = self.saved[i] {
+                    self.calibration[i].offsets = offsets;
+                } else if use_origin {
+                    self.calibration[i].set_origin(raw);
+                }
+                if calibrate_on_connect {
+                    self.calibration[i].start();
+                }
+            }
+
+            calibrated[i] = self.calibration[i].add_sample(raw);
+        }
+
+        calibrated
+    }
+
+    /// Get the origin of the sticks of the controller connected to the given channel (stick X, stick Y, C-stick X
+    /// and C-stick Y). The adapter does not report the origin that the controller sends when it connects, so this is
+    /// the first read after the controller connected, when the sticks are usually at rest.
+    pub fn origin<T>(&self, channel: T) -> [u8; 4]
+    where
+        T: TryInto<Channel>,
+        <T as TryInto<Channel>>::Error: Debug,
+    {
+        self.origins[channel.try_into().unwrap() as usize]
+    }
+
+    /// Start calibrating the sticks of all connected controllers.
+    pub fn calibrate(&mut self) {
+        for i in 0..4 {
+            if self.is_connected(i) {
+                self.calibration[i].start();
+            }
+        }
+    }
+
+    fn raw_sticks(&self, channel: usize) -> [u8; 4] {
+        let start = 9 * channel + 4;
+        self.buf[start..start + 4].try_into().unwrap()
     }
 
     /// Get the `ControllerState` for the given channel
@@ -94,12 +481,15 @@ impl AdapterState {
         <T as TryInto<Channel>>::Error: Debug,
     {
         let channel = channel.try_into().unwrap() as usize;
+        let [stick_x, stick_y, substick_x, substick_y] =
+            self.calibration[channel].apply(self.raw_sticks(channel));
 
-        if let [status, b1, b2, stick_x, stick_y, substick_x, substick_y, trigger_left, trigger_right, ..] =
+        if let [status, b1, b2, _, _, _, _, trigger_left, trigger_right, ..] =
             self.buf[(9 * channel) + 1..]
         {
             ControllerState {
                 connected: is_controller_connected(status),
+                wireless: ControllerKind::from_status(status) == ControllerKind::Wireless,
 
                 a: b1 & (1 << 0) > 0,
                 b: b1 & (1 << 1) > 0,
@@ -136,14 +526,23 @@ impl AdapterState {
     {
         let channel = channel.try_into().unwrap();
 
-        let status = self.buf[1 + (9 * channel as usize)] >> 4;
+        let status = self.buf[1 + (9 * channel as usize)];
         is_controller_connected(status)
     }
 
+    /// Get the kind of controller that is connected to the given channel.
+    pub fn controller_kind<T>(&self, channel: T) -> ControllerKind
+    where
+        T: TryInto<Channel>,
+        <T as TryInto<Channel>>::Error: Debug,
+    {
+        let channel = channel.try_into().unwrap();
+
+        ControllerKind::from_status(self.buf[1 + (9 * channel as usize)])
+    }
+
     pub fn any_connected(&self) -> bool {
-        (0..4)
-            .map(|i| self.is_connected(i))
-            .any(std::convert::identity)
+        (0..4).any(|i| self.is_connected(i))
     }
 }
 
@@ -153,9 +552,84 @@ impl Default for AdapterState {
     }
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+/// Neutral offsets for the sticks of a controller (stick X, stick Y, C-stick X and C-stick Y), found by averaging the
+/// stick values over a number of reads while the sticks are at rest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Calibration {
+    pub offsets: [i8; 4],
+    sums: [i32; 4],
+    samples: u32,
+    running: bool,
+}
+
+impl Calibration {
+    /// The number of reads to average.
+    pub const SAMPLES: u32 = 30;
+
+    pub const fn new() -> Self {
+        Calibration {
+            offsets: [0; 4],
+            sums: [0; 4],
+            samples: 0,
+            running: false,
+        }
+    }
+
+    /// Start sampling new offsets. The current offsets are used until the calibration is finished.
+    pub fn start(&mut self) {
+        self.sums = [0; 4];
+        self.samples = 0;
+        self.running = true;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Use the given stick values as the neutral position.
+    fn set_origin(&mut self, origin: [u8; 4]) {
+        self.offsets = origin.map(|v| (v as i16 - 128).clamp(i8::MIN as i16, i8::MAX as i16) as i8);
+    }
+
+    /// Add the stick values of a read to the calibration, if it is running. Returns true if this finished it.
+    fn add_sample(&mut self, raw: [u8; 4]) -> bool {
+        if !self.running {
+            return false;
+        }
+
+        for (sum, v) in self.sums.iter_mut().zip(raw) {
+            *sum += v as i32 - 128;
+        }
+        self.samples += 1;
+
+        if self.samples == Self::SAMPLES {
+            self.offsets = self.sums.map(|sum| (sum / Self::SAMPLES as i32) as i8);
+            self.running = false;
+        }
+
+        !self.running
+    }
+
+    fn apply(&self, raw: [u8; 4]) -> [u8; 4] {
+        let mut calibrated = raw;
+        for (v, offset) in calibrated.iter_mut().zip(self.offsets) {
+            *v = (*v as i16 - offset as i16).clamp(0, u8::MAX as i16) as u8;
+        }
+        calibrated
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration::new()
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct ControllerState {
     pub connected: bool,
+    /// Whether the controller is wireless, see `ControllerKind`.
+    pub wireless: bool,
 
     pub a: bool,
     pub b: bool,
@@ -181,12 +655,223 @@ pub struct ControllerState {
 }
 
 impl ControllerState {
+    /// Check if the given button is pressed.
+    pub fn is_pressed(&self, button: GcButton) -> bool {
+        let mut state = *self;
+        *state.button(button)
+    }
+
+    /// Describe the stick, trigger and button values, for diagnostics.
+    pub fn summary(&self) -> String {
+        let pressed = GcButton::ALL
+            .into_iter()
+            .filter(|&b| self.is_pressed(b))
+            .map(|b| format!("{:?}", b))
+            .collect::<Vec<_>>();
+
+        format!(
+            "stick {} {}, C-stick {} {}, triggers {} {}, buttons [{}]",
+            self.stick_x,
+            self.stick_y,
+            self.substick_x,
+            self.substick_y,
+            self.trigger_left,
+            self.trigger_right,
+            pressed.join(", ")
+        )
+    }
+
+    /// Make the given button read as released. For L and R this also releases the analog trigger.
+    pub fn release(&mut self, button: GcButton) {
+        *self.button(button) = false;
+        match button {
+            GcButton::L => self.trigger_left = 0,
+            GcButton::R => self.trigger_right = 0,
+            _ => {}
+        }
+    }
+
+    fn button(&mut self, button: GcButton) -> &mut bool {
+        match button {
+            GcButton::A => &mut self.a,
+            GcButton::B => &mut self.b,
+            GcButton::X => &mut self.x,
+            GcButton::Y => &mut self.y,
+            GcButton::Start => &mut self.start,
+            GcButton::Z => &mut self.z,
+            GcButton::L => &mut self.l,
+            GcButton::R => &mut self.r,
+            GcButton::DPadLeft => &mut self.left,
+            GcButton::DPadRight => &mut self.right,
+            GcButton::DPadDown => &mut self.down,
+            GcButton::DPadUp => &mut self.up,
+        }
+    }
+
+    fn to_bits(self) -> u64 {
+        let buttons = [
+            self.connected,
+            self.a,
+            self.b,
+            self.x,
+            self.y,
+            self.left,
+            self.right,
+            self.down,
+            self.up,
+            self.start,
+            self.z,
+            self.r,
+            self.l,
+            self.wireless,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0u16, |bits, (i, &pressed)| bits | (pressed as u16) << i);
+
+        let [lo, hi] = buttons.to_le_bytes();
+        u64::from_le_bytes([
+            lo,
+            hi,
+            self.stick_x,
+            self.stick_y,
+            self.substick_x,
+            self.substick_y,
+            self.trigger_left,
+            self.trigger_right,
+        ])
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        let [lo, hi, stick_x, stick_y, substick_x, substick_y, trigger_left, trigger_right] =
+            bits.to_le_bytes();
+        let buttons = u16::from_le_bytes([lo, hi]);
+        let bit = |i: u16| buttons & (1 << i) != 0;
+
+        ControllerState {
+            connected: bit(0),
+            a: bit(1),
+            b: bit(2),
+            x: bit(3),
+            y: bit(4),
+            left: bit(5),
+            right: bit(6),
+            down: bit(7),
+            up: bit(8),
+            start: bit(9),
+            z: bit(10),
+            r: bit(11),
+            l: bit(12),
+            wireless: bit(13),
+            stick_x,
+            stick_y,
+            substick_x,
+            substick_y,
+            trigger_left,
+            trigger_right,
+        }
+    }
+
+    /// Combine the connected controllers in `states` into one with the given mode. Returns a disconnected state if
+    /// none of them are connected. `MergeMode::Separate` is not a way to combine controllers, and uses the first one
+    /// like `MergeMode::FirstWins`.
+    pub fn merge<I>(states: I, mode: MergeMode) -> ControllerState
+    where
+        I: IntoIterator<Item = ControllerState>,
+    {
+        let mut connected = states.into_iter().filter(|s| s.connected);
+
+        match mode {
+            MergeMode::Separate | MergeMode::FirstWins => connected.next().unwrap_or_default(),
+            MergeMode::LastWins => connected.last().unwrap_or_default(),
+            MergeMode::Or => connected.reduce(ControllerState::or).unwrap_or_default(),
+        }
+    }
+
+    /// Combine two connected controllers for `MergeMode::Or`.
+    fn or(self, other: ControllerState) -> ControllerState {
+        let distance = |x: u8, y: u8| (x as i32 - 128).pow(2) + (y as i32 - 128).pow(2);
+        let stick = if distance(other.stick_x, other.stick_y) > distance(self.stick_x, self.stick_y)
+        {
+            other
+        } else {
+            self
+        };
+        let c_stick = if distance(other.substick_x, other.substick_y)
+            > distance(self.substick_x, self.substick_y)
+        {
+            other
+        } else {
+            self
+        };
+
+        let mut merged = ControllerState {
+            // Rumble is only turned off when no controller can rumble
+            wireless: self.wireless && other.wireless,
+            stick_x: stick.stick_x,
+            stick_y: stick.stick_y,
+            substick_x: c_stick.substick_x,
+            substick_y: c_stick.substick_y,
+            trigger_left: self.trigger_left.max(other.trigger_left),
+            trigger_right: self.trigger_right.max(other.trigger_right),
+            ..self
+        };
+        for button in GcButton::ALL {
+            *merged.button(button) |= other.is_pressed(button);
+        }
+
+        merged
+    }
+
+    /// Swap the values of the control stick and the C-stick.
+    pub fn swap_sticks(&mut self) {
+        std::mem::swap(&mut self.stick_x, &mut self.substick_x);
+        std::mem::swap(&mut self.stick_y, &mut self.substick_y);
+    }
+
     pub fn stick_with_deadzone(&self, deadzone: u8, sensitivity: u8) -> (i8, i8) {
+        self.stick_with_deadzone_shape(deadzone, sensitivity, DeadzoneShape::Radial)
+    }
+
+    pub fn stick_with_deadzone_shape(
+        &self,
+        deadzone: u8,
+        sensitivity: u8,
+        shape: DeadzoneShape,
+    ) -> (i8, i8) {
         const STICK_MAX: i32 = i8::MAX as i32;
 
         let x = self.stick_x.wrapping_add(128) as i8;
         let y = self.stick_y.wrapping_add(128) as i8;
 
+        let deadzone = deadzone as i32;
+        // User-facing sensitivity is inverted (so that higher values give higher radius)
+        let sensitivity = u8::MAX as i32 - sensitivity as i32;
+
+        // Scale a distance from the center to counteract the deadzone, and fit it to the range [-80, 80] (N64
+        // stick range).
+        // This formula is a simplified version of the following:
+        //
+        // let radius = (radius - deadzone as f32) * (STICK_MAX as f32 / (STICK_MAX - deadzone) as f32);
+        // let radius = radius * 80.0 / (STICK_MAX as f32 * (sensitivity as f32 / 100.0)) as f32;
+        let scale = |distance: f32| {
+            8000.0 * (distance - deadzone as f32)
+                / (sensitivity * (STICK_MAX - deadzone).max(1)) as f32
+        };
+        let in_deadzone = |v: i8| v.unsigned_abs() as i32 <= deadzone;
+
+        if shape == DeadzoneShape::Axial {
+            let axis = |v: i8| {
+                if in_deadzone(v) {
+                    0
+                } else {
+                    (v.signum() as f32 * scale(v.unsigned_abs() as f32)).round() as i8
+                }
+            };
+
+            return (axis(x), axis(y));
+        }
+
         // Convert cartesian coordinates to polar coordinates (radius)
         let radius = ((x as f32).powi(2) + (y as f32).powi(2)).sqrt();
 
@@ -197,24 +882,22 @@ impl ControllerState {
         // Convert cartesian coordinates to polar coordinates (angle)
         let angle = (y as f32).atan2(x as f32);
 
-        let deadzone = deadzone as i32;
-        // User-facing sensitivity is inverted (so that higher values give higher radius)
-        let sensitivity = u8::MAX as i32 - sensitivity as i32;
-
-        // Scale radius to counteract the deadzone, and fit the radius to the range [-80, 80] (N64
-        // stick range).
-        // This formula is a simplified version of the following:
-        //
-        // let radius = (radius - deadzone as f32) * (STICK_MAX as f32 / (STICK_MAX - deadzone) as f32);
-        // let radius = radius * 80.0 / (STICK_MAX as f32 * (sensitivity as f32 / 100.0)) as f32;
-        let radius =
-            8000.0 * (radius - deadzone as f32) / (sensitivity * (STICK_MAX - deadzone)) as f32;
+        let radius = scale(radius);
 
         // Convert back to cartesian coordinates
-        let x = (radius * angle.cos()).round() as i8;
-        let y = (radius * angle.sin()).round() as i8;
+        let out_x = (radius * angle.cos()).round() as i8;
+        let out_y = (radius * angle.sin()).round() as i8;
 
-        (x, y)
+        if shape == DeadzoneShape::Cross {
+            // Snap to the closest axis when the other axis is within the deadzone
+            if in_deadzone(x) {
+                return (0, (y.signum() as f32 * radius).round() as i8);
+            } else if in_deadzone(y) {
+                return ((x.signum() as f32 * radius).round() as i8, 0);
+            }
+        }
+
+        (out_x, out_y)
     }
 
     pub fn substick_with_deadzone(&self, deadzone: u8) -> (i8, i8) {
@@ -227,16 +910,260 @@ impl ControllerState {
 
         (x, y)
     }
+
+    /// Get the direction of the C-stick on each axis (-1, 0 or 1), where a direction is active when the raw value is
+    /// at or below the low threshold of its axis in `x` or `y`, or at or above its high threshold. A direction that was
+    /// active in `previous` stays active until the C-stick moves `hysteresis` past its threshold back towards the
+    /// center, so that a C-stick held close to a threshold does not toggle the direction.
+    pub fn substick_with_thresholds(
+        &self,
+        x: (u8, u8),
+        y: (u8, u8),
+        hysteresis: u8,
+        previous: (i8, i8),
+    ) -> (i8, i8) {
+        let direction = |v: u8, (low, high): (u8, u8), previous: i8| {
+            if previous < 0 && v <= low.saturating_add(hysteresis) {
+                -1
+            } else if previous > 0 && v >= high.saturating_sub(hysteresis) {
+                1
+            } else if v <= low {
+                -1
+            } else if v >= high {
+                1
+            } else {
+                0
+            }
+        };
+
+        (
+            direction(self.substick_x, x, previous.0),
+            direction(self.substick_y, y, previous.1),
+        )
+    }
+}
+
+/// A `ControllerState` that can be shared between threads without locking, by packing it into a single atomic.
+#[derive(Debug, Default)]
+pub struct AtomicControllerState(AtomicU64);
+
+impl AtomicControllerState {
+    /// Create a state without a connected controller.
+    pub const fn new() -> Self {
+        AtomicControllerState(AtomicU64::new(0))
+    }
+
+    pub fn load(&self) -> ControllerState {
+        ControllerState::from_bits(self.0.load(Ordering::Acquire))
+    }
+
+    pub fn store(&self, state: ControllerState) {
+        self.0.store(state.to_bits(), Ordering::Release);
+    }
+}
+
+/// Find the adapter (by index in `states`) and the port that each N64 channel reads from.
+///
+/// A channel reads its port in `sources` of the adapter it is pinned to, by the adapter's ID in `ids`, so that it
+/// keeps reading from the same adapter when adapters are reconnected in a different order. An unpinned channel reads
+/// its port of the first adapter if a controller is connected there, and otherwise the first connected controller on
+/// the other adapters that no other channel reads from.
+pub fn map_channels(
+    states: &[AdapterState],
+    ids: &[&str],
+    pinned: [Option<&str>; 4],
+    sources: [Channel; 4],
+) -> [Option<(usize, Channel)>; 4] {
+    let channels = [Channel::One, Channel::Two, Channel::Three, Channel::Four];
+    let mut mapping = [None; 4];
+
+    for (i, &channel) in sources.iter().enumerate() {
+        mapping[i] = match pinned[i] {
+            Some(id) => ids
+                .iter()
+                .position(|&a| a == id)
+                .filter(|&adapter| adapter < states.len())
+                .map(|adapter| (adapter, channel)),
+            None => states
+                .first()
+                .filter(|s| s.is_connected(channel))
+                .map(|_| (0, channel)),
+        };
+    }
+
+    let mut free = (1..states.len())
+        .flat_map(|adapter| channels.iter().map(move |&channel| (adapter, channel)))
+        .filter(|&(adapter, channel)| states[adapter].is_connected(channel))
+        .filter(|source| !mapping.contains(&Some(*source)))
+        .collect::<Vec<_>>()
+        .into_iter();
+
+    for (i, source) in mapping.iter_mut().enumerate() {
+        if source.is_none() && pinned[i].is_none() {
+            *source = free.next();
+        }
+    }
+
+    mapping
+}
+
+/// Get the channels that each port of `adapters` adapters rumbles for, one bit per channel, from the adapter and port
+/// that each channel reads from. A port rumbles while any of the channels that read from it rumbles.
+pub fn rumble_routes(adapters: usize, sources: &[(Channel, (usize, Channel))]) -> Vec<[u8; 4]> {
+    let mut routes = vec![[0; 4]; adapters];
+    for &(channel, (adapter, port)) in sources {
+        if let Some(ports) = routes.get_mut(adapter) {
+            ports[port as usize] |= 1 << channel as usize;
+        }
+    }
+    routes
+}
+
+/// Tracks whether any adapter is connected, so that changes are only announced once the connection is stable.
+#[derive(Debug)]
+pub struct ConnectionStatus {
+    connected: bool,
+    announced: bool,
+    changed_at: Instant,
+}
+
+impl ConnectionStatus {
+    /// How long the connection has to be stable before it is announced.
+    const DEBOUNCE: Duration = Duration::from_millis(500);
+
+    pub fn new(now: Instant) -> Self {
+        ConnectionStatus {
+            connected: false,
+            announced: false,
+            changed_at: now,
+        }
+    }
+
+    /// Update whether any adapter is connected, returning the connection state if it should be announced.
+    pub fn update(&mut self, connected: bool, now: Instant) -> Option<bool> {
+        if connected != self.connected {
+            self.connected = connected;
+            self.changed_at = now;
+        }
+
+        let stable = now.duration_since(self.changed_at) >= Self::DEBOUNCE;
+        if stable && self.connected != self.announced {
+            self.announced = self.connected;
+            return Some(self.connected);
+        }
+
+        None
+    }
+}
+
+/// Exponential backoff for reconnecting to adapters that keep getting lost, e.g. with a loose cable, so that they are
+/// not opened again and again in quick succession.
+#[derive(Debug, Default)]
+pub struct ReconnectBackoff {
+    /// How long to wait before looking for adapters again, or `None` while no adapter was lost recently.
+    interval: Option<Duration>,
+    /// Since when an adapter has been connected without being lost.
+    connected_since: Option<Instant>,
+}
+
+impl ReconnectBackoff {
+    /// The wait after the first lost adapter, which doubles every time an adapter is lost again.
+    pub const INITIAL_INTERVAL: Duration = Duration::from_millis(200);
+
+    pub const fn new() -> Self {
+        ReconnectBackoff {
+            interval: None,
+            connected_since: None,
+        }
+    }
+
+    /// Record that an adapter was lost, and get how long to wait before looking for adapters again.
+    pub fn lost(&mut self, max: Duration) -> Duration {
+        let interval = self
+            .interval
+            .map_or(Self::INITIAL_INTERVAL, |interval| interval * 2)
+            .min(max);
+        self.interval = Some(interval);
+        self.connected_since = None;
+        interval
+    }
+
+    /// Get how long to wait before looking for adapters again while none are found, if an adapter was lost recently.
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// Update whether any adapter is connected. The backoff is reset once an adapter has been connected for `max`.
+    pub fn update(&mut self, connected: bool, now: Instant, max: Duration) {
+        if !connected {
+            self.connected_since = None;
+            return;
+        }
+
+        let since = *self.connected_since.get_or_insert(now);
+        if now.duration_since(since) >= max {
+            self.interval = None;
+        }
+    }
+}
+
+/// Find the interface and alternate setting with the input endpoint. This is the first setting of interface 0 on the
+/// known adapters, which is also used if the configuration cannot be read.
+fn input_interface(device: &Device<GlobalContext>) -> (u8, u8) {
+    device
+        .active_config_descriptor()
+        .ok()
+        .and_then(|config| {
+            config
+                .interfaces()
+                .flat_map(|interface| interface.descriptors())
+                .find(|desc| {
+                    desc.endpoint_descriptors()
+                        .any(|endpoint| endpoint.address() == ENDPOINT_IN)
+                })
+                .map(|desc| (desc.interface_number(), desc.setting_number()))
+        })
+        .unwrap_or((0, 0))
+}
+
+fn is_gc_adapter(device: &Device<GlobalContext>) -> bool {
+    device
+        .device_descriptor()
+        .map(|desc| KNOWN_ADAPTERS.contains(&(desc.vendor_id(), desc.product_id())))
+        .unwrap_or(false)
+}
+
+/// Check that a report from the adapter has the full length and starts with the input report header.
+fn is_valid_report(report: &[u8]) -> bool {
+    report.len() == READ_LEN && report[0] == REPORT_HEADER
+}
+
+/// The kind of controller that is connected to a port of the adapter.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControllerKind {
+    None,
+    /// A wired controller, which can rumble.
+    Wired,
+    /// A wireless controller like the WaveBird, which has no rumble motor.
+    Wireless,
+}
+
+impl ControllerKind {
+    fn from_status(status: u8) -> Self {
+        // The type is in bits 4 and 5 of the status byte
+        match status & (0x10 | 0x20) {
+            0x10 => ControllerKind::Wired,
+            0x20 => ControllerKind::Wireless,
+            _ => ControllerKind::None,
+        }
+    }
 }
 
 fn is_controller_connected(status: u8) -> bool {
-    // 0x10 = Normal
-    // 0x20 = Wavebird
-    let controller_type = status & (0x10 | 0x20);
-    controller_type == 0x10 || controller_type == 0x20
+    ControllerKind::from_status(status) != ControllerKind::None
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Channel {
     One = 0,
     Two = 1,
@@ -282,4 +1209,406 @@ mod tests {
         let mut state = AdapterState::new();
         state.buf.copy_from_slice(&data);
     }
+
+    #[test]
+    fn test_valid_report() {
+        let mut report = [0; READ_LEN];
+        report[0] = REPORT_HEADER;
+        assert!(is_valid_report(&report));
+        assert!(!is_valid_report(&report[..READ_LEN - 1]));
+        assert!(!is_valid_report(&[]));
+
+        report[0] = 0x00;
+        assert!(!is_valid_report(&report));
+    }
+
+    fn connected_state(channels: &[usize]) -> AdapterState {
+        let mut state = AdapterState::new();
+        for &ch in channels {
+            state.buf[1 + 9 * ch] = 0x10;
+        }
+        state
+    }
+
+    #[test]
+    fn test_calibration() {
+        let mut buf = connected_state(&[0, 1]).buf;
+        for ch in 0..2 {
+            buf[9 * ch + 4..9 * ch + 8].fill(128);
+        }
+        // Stick X and C-stick Y of channel 1 rest off-center
+        buf[4] = 128 + 6;
+        buf[7] = 128 - 10;
+
+        let mut state = AdapterState::new();
+        for _ in 1..Calibration::SAMPLES {
+            assert_eq!(state.update(buf, false, true), [false; 4]);
+        }
+        assert_eq!(state.update(buf, false, true), [true, true, false, false]);
+
+        assert_eq!(state.calibration[0].offsets, [6, 0, 0, -10]);
+        assert!(!state.calibration[0].is_running());
+        assert_eq!(state.calibration[1].offsets, [0; 4]);
+
+        let s = state.controller_state(0);
+        assert_eq!(
+            (s.stick_x, s.stick_y, s.substick_x, s.substick_y),
+            (128, 128, 128, 128)
+        );
+    }
+
+    #[test]
+    fn test_origin() {
+        let mut buf = connected_state(&[0]).buf;
+        buf[4..8].copy_from_slice(&[128 + 6, 128, 128, 128 - 10]);
+
+        let mut state = AdapterState::new();
+        state.update(buf, true, false);
+        assert_eq!(state.origin(0), [134, 128, 128, 118]);
+        let s = state.controller_state(0);
+        assert_eq!(
+            (s.stick_x, s.stick_y, s.substick_x, s.substick_y),
+            (128, 128, 128, 128)
+        );
+
+        // Only the first read after connecting is the origin
+        buf[4] = 200;
+        state.update(buf, true, false);
+        assert_eq!(state.origin(0), [134, 128, 128, 118]);
+        assert_eq!(state.controller_state(0).stick_x, 194);
+
+        let mut state = AdapterState::new();
+        state.update(buf, false, false);
+        assert_eq!(state.controller_state(0).stick_x, 200);
+
+        // A saved calibration is used instead of the origin
+        let mut state = AdapterState::with_saved([Some([10, 0, 0, 0]), None, None, None]);
+        state.update(buf, true, false);
+        assert_eq!(state.controller_state(0).stick_x, 190);
+    }
+
+    #[test]
+    fn test_map_channels() {
+        use Channel::*;
+
+        let sources = [One, Two, Three, Four];
+        let ids = ["usb-1-2", "usb-1-3"];
+        let states = [connected_state(&[0, 1]), connected_state(&[0, 1])];
+        assert_eq!(
+            map_channels(&states, &ids, [None; 4], sources),
+            [
+                Some((0, One)),
+                Some((0, Two)),
+                Some((1, One)),
+                Some((1, Two))
+            ]
+        );
+
+        let states = [connected_state(&[0]), connected_state(&[2])];
+        assert_eq!(
+            map_channels(
+                &states,
+                &ids,
+                [None, None, Some("usb-1-3"), Some("usb-1-2")],
+                sources
+            ),
+            [Some((0, One)), None, Some((1, Three)), Some((0, Four))]
+        );
+
+        // Pinned ports follow their adapter when the adapters are connected in a different order
+        let states = [connected_state(&[2]), connected_state(&[3])];
+        assert_eq!(
+            map_channels(
+                &states,
+                &["usb-1-3", "usb-1-2"],
+                [None, None, Some("usb-1-3"), Some("usb-1-2")],
+                sources
+            ),
+            [None, None, Some((0, Three)), Some((1, Four))]
+        );
+
+        let states = [connected_state(&[0, 1, 2, 3])];
+        assert_eq!(
+            map_channels(
+                &states,
+                &ids[..1],
+                [None, None, None, Some("usb-1-3")],
+                sources
+            ),
+            [Some((0, One)), Some((0, Two)), Some((0, Three)), None]
+        );
+
+        let states = [connected_state(&[2])];
+        assert_eq!(
+            map_channels(&states, &ids[..1], [None; 4], [Three, Two, One, Four]),
+            [Some((0, Three)), None, None, None]
+        );
+    }
+
+    #[test]
+    fn test_rumble_routes() {
+        use Channel::*;
+
+        // Two players on each of two adapters
+        let sources = [
+            (One, (0, One)),
+            (Two, (0, Two)),
+            (Three, (1, One)),
+            (Four, (1, Two)),
+        ];
+        assert_eq!(
+            rumble_routes(2, &sources),
+            [[0b0001, 0b0010, 0, 0], [0b0100, 0b1000, 0, 0]]
+        );
+
+        // Channels that read from the same port, and adapters that are gone
+        let sources = [(One, (0, Three)), (Two, (0, Three)), (Three, (1, One))];
+        assert_eq!(rumble_routes(1, &sources), [[0, 0, 0b0011, 0]]);
+    }
+
+    #[test]
+    fn test_deadzone_shapes() {
+        use DeadzoneShape::*;
+
+        // Mostly to the right, slightly up (raw values are offset by 128)
+        let state = ControllerState {
+            stick_x: 128 + 100,
+            stick_y: 128 + 15,
+            ..Default::default()
+        };
+
+        let (_, radial_y) = state.stick_with_deadzone_shape(20, 155, Radial);
+        assert!(radial_y > 0);
+        assert_eq!(state.stick_with_deadzone_shape(20, 155, Axial).1, 0);
+        assert_eq!(state.stick_with_deadzone_shape(20, 155, Cross).1, 0);
+
+        // The cross deadzone keeps the full radius when snapping to an axis
+        let (cross_x, _) = state.stick_with_deadzone_shape(20, 155, Cross);
+        let (axial_x, _) = state.stick_with_deadzone_shape(20, 155, Axial);
+        assert!(cross_x > axial_x);
+
+        let center = ControllerState {
+            stick_x: 128 + 10,
+            stick_y: 128 - 10,
+            ..Default::default()
+        };
+        for shape in [Radial, Axial, Cross] {
+            assert_eq!(center.stick_with_deadzone_shape(20, 155, shape), (0, 0));
+        }
+    }
+
+    #[test]
+    fn test_substick_thresholds() {
+        let state = |substick_x, substick_y| ControllerState {
+            substick_x,
+            substick_y,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            state(128, 128).substick_with_thresholds((88, 168), (88, 168), 0, (0, 0)),
+            (0, 0)
+        );
+        assert_eq!(
+            state(88, 168).substick_with_thresholds((88, 168), (88, 168), 0, (0, 0)),
+            (-1, 1)
+        );
+        assert_eq!(
+            state(80, 175).substick_with_thresholds((70, 186), (70, 186), 0, (0, 0)),
+            (0, 0)
+        );
+
+        // Active directions are released past the hysteresis, inactive ones still need the threshold
+        assert_eq!(
+            state(95, 162).substick_with_thresholds((88, 168), (88, 168), 8, (-1, 1)),
+            (-1, 1)
+        );
+        assert_eq!(
+            state(97, 159).substick_with_thresholds((88, 168), (88, 168), 8, (-1, 1)),
+            (0, 0)
+        );
+        assert_eq!(
+            state(95, 162).substick_with_thresholds((88, 168), (88, 168), 8, (0, 0)),
+            (0, 0)
+        );
+        assert_eq!(
+            state(200, 50).substick_with_thresholds((88, 168), (88, 168), 8, (-1, 1)),
+            (1, -1)
+        );
+
+        // The axes have their own thresholds
+        assert_eq!(
+            state(80, 100).substick_with_thresholds((70, 186), (100, 150), 0, (0, 0)),
+            (0, -1)
+        );
+    }
+
+    #[test]
+    fn test_channel_from_control() {
+        for control in [i32::MIN, -1, 4, 5, i32::MAX] {
+            assert_eq!(Channel::try_from(control), Err(control));
+        }
+        assert_eq!(Channel::try_from(0), Ok(Channel::One));
+        assert_eq!(Channel::try_from(3), Ok(Channel::Four));
+        assert_eq!(Channel::try_from(4usize), Err(4));
+    }
+
+    #[test]
+    fn test_controller_kind() {
+        let mut state = AdapterState::new();
+        state.buf[1] = 0x14;
+        state.buf[1 + 9] = 0x24;
+        state.buf[1 + 27] = 0x30;
+
+        assert_eq!(state.controller_kind(0), ControllerKind::Wired);
+        assert_eq!(state.controller_kind(1), ControllerKind::Wireless);
+        assert_eq!(state.controller_kind(2), ControllerKind::None);
+        assert_eq!(state.controller_kind(3), ControllerKind::None);
+        assert!(state.controller_state(1).wireless && !state.controller_state(0).wireless);
+    }
+
+    #[test]
+    fn test_merge() {
+        let first = ControllerState {
+            connected: true,
+            a: true,
+            stick_x: 200,
+            stick_y: 128,
+            substick_x: 128,
+            substick_y: 128,
+            trigger_left: 40,
+            ..Default::default()
+        };
+        let second = ControllerState {
+            connected: true,
+            b: true,
+            stick_x: 100,
+            stick_y: 128,
+            substick_x: 128,
+            substick_y: 20,
+            trigger_left: 10,
+            trigger_right: 90,
+            ..Default::default()
+        };
+        let disconnected = ControllerState {
+            a: true,
+            ..Default::default()
+        };
+        let merge = |mode| ControllerState::merge([disconnected, first, second], mode);
+
+        assert_eq!(merge(MergeMode::FirstWins), first);
+        assert_eq!(merge(MergeMode::LastWins), second);
+        let or = merge(MergeMode::Or);
+        assert!(or.connected && or.a && or.b);
+        assert_eq!((or.stick_x, or.substick_y), (200, 20));
+        assert_eq!((or.trigger_left, or.trigger_right), (40, 90));
+        assert!(!ControllerState::merge([disconnected], MergeMode::Or).connected);
+    }
+
+    #[test]
+    fn test_atomic_controller_state() {
+        let state = ControllerState {
+            connected: true,
+            wireless: true,
+            a: true,
+            up: true,
+            l: true,
+            stick_x: 1,
+            stick_y: 2,
+            substick_x: 3,
+            substick_y: 4,
+            trigger_left: 5,
+            trigger_right: 255,
+            ..Default::default()
+        };
+
+        let atomic = AtomicControllerState::new();
+        assert!(!atomic.load().connected);
+
+        atomic.store(state);
+        assert_eq!(atomic.load(), state);
+    }
+
+    #[test]
+    fn test_connection_status() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut status = ConnectionStatus::new(start);
+
+        assert_eq!(status.update(true, at(0)), None);
+        assert_eq!(status.update(true, at(600)), Some(true));
+        assert_eq!(status.update(true, at(700)), None);
+
+        // A short disconnect is not announced
+        assert_eq!(status.update(false, at(800)), None);
+        assert_eq!(status.update(true, at(900)), None);
+        assert_eq!(status.update(true, at(2000)), None);
+
+        assert_eq!(status.update(false, at(2100)), None);
+        assert_eq!(status.update(false, at(2600)), Some(false));
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let max = Duration::from_millis(1000);
+        let mut backoff = ReconnectBackoff::new();
+        assert_eq!(backoff.interval(), None);
+
+        let waits: Vec<_> = (0..5).map(|_| backoff.lost(max).as_millis()).collect();
+        assert_eq!(waits, [200, 400, 800, 1000, 1000]);
+        assert_eq!(backoff.interval(), Some(max));
+
+        // Staying connected for the longest wait resets the backoff
+        backoff.update(true, at(0), max);
+        backoff.update(true, at(900), max);
+        assert_eq!(backoff.interval(), Some(max));
+        backoff.update(true, at(1000), max);
+        assert_eq!(backoff.interval(), None);
+        assert_eq!(backoff.lost(max), ReconnectBackoff::INITIAL_INTERVAL);
+    }
+
+    #[test]
+    fn test_rumble_report() {
+        assert_eq!(RumbleWriter::report([false; 4]), [0x11, 0, 0, 0, 0]);
+        assert_eq!(
+            RumbleWriter::report([true, false, false, true]),
+            [0x11, 1, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_release_button() {
+        let mut state = ControllerState {
+            r: true,
+            trigger_right: 255,
+            z: true,
+            ..Default::default()
+        };
+        assert!(state.is_pressed(GcButton::R));
+
+        state.release(GcButton::R);
+        assert!(!state.is_pressed(GcButton::R));
+        assert_eq!(state.trigger_right, 0);
+        assert!(state.is_pressed(GcButton::Z));
+    }
+
+    #[test]
+    fn test_summary() {
+        let state = ControllerState {
+            a: true,
+            up: true,
+            stick_x: 200,
+            stick_y: 128,
+            substick_x: 128,
+            substick_y: 60,
+            trigger_left: 30,
+            ..Default::default()
+        };
+        assert_eq!(
+            state.summary(),
+            "stick 200 128, C-stick 128 60, triggers 30 0, buttons [A, DPadUp]"
+        );
+    }
 }
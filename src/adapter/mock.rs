@@ -0,0 +1,136 @@
+//! An adapter that replays recorded input reports, for testing without an adapter.
+
+use super::{AdapterSource, RumbleWriter, READ_LEN};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// Replays a list of input reports, one per read. The last report is repeated once all reports have been read.
+#[derive(Debug)]
+pub struct MockAdapter {
+    reports: Vec<[u8; READ_LEN]>,
+    next: AtomicUsize,
+}
+
+impl MockAdapter {
+    /// The time between two reports, like the polling rate of an adapter.
+    const REPORT_INTERVAL: Duration = Duration::from_millis(1);
+
+    pub fn new(reports: Vec<[u8; READ_LEN]>) -> Self {
+        MockAdapter {
+            reports,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Parse a script with one report per line, as hex bytes separated by whitespace. Empty lines and lines starting
+    /// with `#` are skipped.
+    pub fn from_script(script: &str) -> Result<Self, String> {
+        let reports = script
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line_number, line)| {
+                let bytes = line
+                    .split_whitespace()
+                    .map(|byte| u8::from_str_radix(byte, 16))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("line {line_number}: {e}"))?;
+
+                bytes.try_into().map_err(|bytes: Vec<u8>| {
+                    format!(
+                        "line {line_number}: expected {READ_LEN} bytes, found {}",
+                        bytes.len()
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(MockAdapter::new(reports))
+    }
+}
+
+impl AdapterSource for MockAdapter {
+    fn read_blocking(&self, timeout: Duration) -> rusb::Result<Option<[u8; READ_LEN]>> {
+        thread::sleep(timeout.min(Self::REPORT_INTERVAL));
+
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        let report = self
+            .reports
+            .get(next)
+            .or_else(|| self.reports.last())
+            .copied();
+
+        Ok(report)
+    }
+
+    fn rumble_writer(&self) -> Option<RumbleWriter> {
+        None
+    }
+
+    fn usb_address(&self) -> Option<(u8, u8)> {
+        None
+    }
+
+    fn id(&self) -> &str {
+        "mock"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::{AdapterState, Channel};
+
+    fn report(status: u8, b1: u8) -> [u8; READ_LEN] {
+        let mut report = [0; READ_LEN];
+        report[1] = status;
+        report[2] = b1;
+        report
+    }
+
+    #[test]
+    fn replays_reports() {
+        let mock = MockAdapter::new(vec![report(0x10, 0x01), report(0x10, 0x00)]);
+        let mut state = AdapterState::new();
+
+        state.update(
+            mock.read_blocking(Duration::ZERO).unwrap().unwrap(),
+            false,
+            false,
+        );
+        assert!(state.controller_state(Channel::One).a);
+
+        state.update(
+            mock.read_blocking(Duration::ZERO).unwrap().unwrap(),
+            false,
+            false,
+        );
+        assert!(!state.controller_state(Channel::One).a);
+        assert!(state.controller_state(Channel::One).connected);
+
+        // The last report is repeated
+        assert_eq!(
+            mock.read_blocking(Duration::ZERO).unwrap(),
+            Some(report(0x10, 0x00))
+        );
+    }
+
+    #[test]
+    fn parses_scripts() {
+        let mut line = vec!["00"; READ_LEN];
+        line[1] = "10";
+        line[2] = "ff";
+        let script = format!("# Comment\n\n{}\n", line.join(" "));
+
+        let mock = MockAdapter::from_script(&script).unwrap();
+        assert_eq!(mock.reports, vec![report(0x10, 0xFF)]);
+
+        assert!(MockAdapter::from_script("00 01").is_err());
+        assert!(MockAdapter::from_script("zz").is_err());
+        assert!(MockAdapter::from_script("").unwrap().reports.is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+//! A safe wrapper around the `BUTTONS` union of the emulator, for building the input of a port.
+
+use crate::{config::N64Button, ffi::BUTTONS};
+
+/// The N64 buttons and stick position of a port, laid out like `BUTTONS`: the buttons are the lower two bytes, and
+/// the X and Y axis of the stick are the upper two bytes.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct N64Buttons(u32);
+
+impl N64Buttons {
+    /// The bits of the buttons, without the stick.
+    pub const BUTTONS_MASK: u32 = 0xFFFF;
+
+    /// No buttons pressed, and the stick at the center.
+    pub const fn new() -> Self {
+        N64Buttons(0)
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        N64Buttons(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Get the bit pattern of the pressed buttons.
+    pub const fn buttons(self) -> u32 {
+        self.0 & Self::BUTTONS_MASK
+    }
+
+    pub fn press(&mut self, button: N64Button) {
+        self.0 |= button.bit_pattern();
+    }
+
+    /// Press the buttons of the given bit pattern, like the one of `N64Button::bit_pattern`.
+    pub fn press_bits(&mut self, buttons: u32) {
+        self.0 |= buttons & Self::BUTTONS_MASK;
+    }
+
+    pub fn is_pressed(self, button: N64Button) -> bool {
+        self.0 & button.bit_pattern() != 0
+    }
+
+    pub const fn x_axis(self) -> i8 {
+        (self.0 >> 16) as i8
+    }
+
+    pub const fn y_axis(self) -> i8 {
+        (self.0 >> 24) as i8
+    }
+
+    pub fn set_x_axis(&mut self, x: i8) {
+        self.0 = (self.0 & !0x00FF_0000) | (x as u8 as u32) << 16;
+    }
+
+    pub fn set_y_axis(&mut self, y: i8) {
+        self.0 = (self.0 & !0xFF00_0000) | (y as u8 as u32) << 24;
+    }
+}
+
+impl From<N64Buttons> for BUTTONS {
+    fn from(buttons: N64Buttons) -> Self {
+        BUTTONS { Value: buttons.0 }
+    }
+}
+
+impl From<BUTTONS> for N64Buttons {
+    fn from(buttons: BUTTONS) -> Self {
+        // SAFETY: Every bit pattern is a valid `u32`, and the bitfield has the same size
+        N64Buttons(unsafe { buttons.Value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_buttons() {
+        let mut buttons = N64Buttons::new();
+        buttons.press(N64Button::A);
+        buttons.press(N64Button::CUp);
+        buttons.set_x_axis(-80);
+        buttons.set_y_axis(80);
+        buttons.set_x_axis(-1);
+
+        assert_eq!(buttons.buttons(), 0x0880);
+        assert!(buttons.is_pressed(N64Button::A));
+        assert!(!buttons.is_pressed(N64Button::B));
+        assert_eq!((buttons.x_axis(), buttons.y_axis()), (-1, 80));
+
+        let keys = BUTTONS::from(buttons);
+        unsafe {
+            assert_eq!(keys.__bindgen_anon_1.X_AXIS() as i8, -1);
+            assert_eq!(keys.__bindgen_anon_1.Y_AXIS() as i8, 80);
+            assert_eq!(keys.__bindgen_anon_1.A_BUTTON(), 1);
+            assert_eq!(keys.__bindgen_anon_1.U_CBUTTON(), 1);
+        }
+        assert_eq!(N64Buttons::from(keys), buttons);
+    }
+}
@@ -0,0 +1,133 @@
+//! Stick calibrations that are kept between runs of the emulator.
+//!
+//! The calibration file is a TOML file with a table for each adapter, keyed by the adapter's serial number (or its
+//! USB port when it has none), with the stick offsets (stick X, stick Y, C-stick X and C-stick Y) of each calibrated
+//! port:
+//!
+//! ```toml
+//! ["usb-1-2"]
+//! port_1 = [6, 0, 0, -10]
+//! ```
+
+use crate::debug::M64Message;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The name of the calibration file, which is kept next to the configuration file.
+pub const FILE_NAME: &str = "mupen64plus-input-gca-calibration.toml";
+
+/// The stick offsets of each calibrated port, and the file that they are saved to.
+#[derive(Debug)]
+pub struct CalibrationFile {
+    path: PathBuf,
+    adapters: BTreeMap<String, BTreeMap<String, [i8; 4]>>,
+}
+
+impl CalibrationFile {
+    /// Read the calibrations from the given file. A missing or invalid file is logged, and no port is calibrated.
+    pub fn load<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let adapters = match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).unwrap_or_else(|e| {
+                debug_print!(
+                    M64Message::Warning,
+                    "Could not read calibration file {}, using the default calibration: {}",
+                    path.display(),
+                    e
+                );
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug_print!(
+                    M64Message::Info,
+                    "No calibration file at {}",
+                    path.display()
+                );
+                BTreeMap::new()
+            }
+            Err(e) => {
+                debug_print!(
+                    M64Message::Warning,
+                    "Could not read calibration file {}, using the default calibration: {:?}",
+                    path.display(),
+                    e
+                );
+                BTreeMap::new()
+            }
+        };
+
+        CalibrationFile { path, adapters }
+    }
+
+    /// Get the saved offsets of each port (starting from 0) of the given adapter.
+    pub fn offsets(&self, adapter: &str) -> [Option<[i8; 4]>; 4] {
+        let ports = self.adapters.get(adapter);
+        [0, 1, 2, 3].map(|port| ports?.get(&port_key(port)).copied())
+    }
+
+    /// Set the offsets of a port (starting from 0) of the given adapter, and write the calibrations to the file.
+    pub fn save(&mut self, adapter: &str, port: usize, offsets: [i8; 4]) -> io::Result<()> {
+        self.adapters
+            .entry(adapter.to_owned())
+            .or_default()
+            .insert(port_key(port), offsets);
+
+        let s = toml::to_string(&self.adapters)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(&self.path, s)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn port_key(port: usize) -> String {
+    format!("port_{}", port + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_and_loads_offsets() {
+        let path = std::env::temp_dir().join(format!(
+            "mupen64plus-input-gca-calibration-test-{}.toml",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let mut file = CalibrationFile::load(&path);
+        assert_eq!(file.offsets("usb-1-2"), [None; 4]);
+
+        file.save("usb-1-2", 1, [6, 0, 0, -10]).unwrap();
+        file.save("mock", 0, [1, 2, 3, 4]).unwrap();
+
+        let file = CalibrationFile::load(&path);
+        assert_eq!(
+            file.offsets("usb-1-2"),
+            [None, Some([6, 0, 0, -10]), None, None]
+        );
+        assert_eq!(file.offsets("mock"), [Some([1, 2, 3, 4]), None, None, None]);
+        assert_eq!(file.offsets("usb-1-3"), [None; 4]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ignores_invalid_file() {
+        let path = std::env::temp_dir().join(format!(
+            "mupen64plus-input-gca-calibration-invalid-test-{}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, "[\"usb-1-2\"]\nport_1 = [300, 0]").unwrap();
+
+        assert_eq!(CalibrationFile::load(&path).offsets("usb-1-2"), [None; 4]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
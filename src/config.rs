@@ -1,20 +1,332 @@
+use crate::{adapter::Channel, debug::M64Message};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    fmt::Debug,
+    fs,
     fs::File,
     io::{self, Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
-#[derive(Debug, Deserialize, Serialize)]
+/// The bundled default configuration, written to disk when no configuration file exists.
+const DEFAULT_CONFIG: &str = include_str!("../mupen64plus-input-gca.toml");
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
     pub control_stick_deadzone: u8,
+    pub control_stick_deadzone_shape: DeadzoneShape,
     pub control_stick_sensitivity: u8,
+    pub control_stick_scale: f32,
+    pub control_stick_response_curve: f32,
+    pub control_stick_anti_deadzone: f32,
+    pub control_stick_mode: StickMode,
+    /// Whether the control stick also presses the N64 D-pad.
+    pub stick_to_d_pad: StickToDPad,
+    /// How far the control stick has to be moved, in N64 stick units, to press the D-pad with `stick_to_d_pad`.
+    pub stick_to_d_pad_threshold: u8,
+    pub control_stick_gate: StickGate,
+    pub control_stick_gate_radius: f32,
+    /// The largest value of each N64 stick axis, for games that do not handle the full range well.
+    pub control_stick_max: i8,
+    pub control_stick_invert_x: bool,
+    pub control_stick_invert_y: bool,
+    pub control_stick_smoothing: f32,
+    pub c_stick_smoothing: f32,
     pub c_stick_deadzone: u8,
+    pub c_stick_threshold_low: Option<u8>,
+    pub c_stick_threshold_high: Option<u8>,
+    /// The [low, high] thresholds of the C-stick X axis, overriding `c_stick_threshold_low` and `_high`.
+    pub c_stick_threshold_x: Option<[u8; 2]>,
+    /// The [low, high] thresholds of the C-stick Y axis, overriding `c_stick_threshold_low` and `_high`.
+    pub c_stick_threshold_y: Option<[u8; 2]>,
+    /// How far the C-stick has to move back past a threshold to release its C-button.
+    pub c_stick_hysteresis: u8,
+    pub c_stick_mode: CStickMode,
+    pub c_stick_invert_x: bool,
+    pub c_stick_invert_y: bool,
+    pub swap_sticks: bool,
+    pub d_pad_drives_stick: bool,
+    pub socd_mode: SocdMode,
+    pub use_origin: bool,
+    pub calibrate_on_connect: bool,
+    pub calibrate_key: Option<i32>,
+    pub precision_button: Option<GcButton>,
+    pub precision_button_exclusive: bool,
+    pub precision_scale: f32,
+    /// GameCube button that switches the buttons in `shift_mapping` to their alternate N64 buttons while held.
+    pub shift_button: Option<GcButton>,
+    /// If true, the shift button still presses the N64 button it is mapped to.
+    pub shift_button_passthrough: bool,
+    pub shift_mapping: ShiftMapping,
+    /// N64 buttons that GC buttons press in addition to the button they are mapped to.
+    pub extra_buttons: ExtraButtons,
     pub trigger_threshold: u8,
+    pub trigger_threshold_left: Option<u8>,
+    pub trigger_threshold_right: Option<u8>,
+    pub trigger_range: [u8; 2],
+    pub trigger_range_left: Option<[u8; 2]>,
+    pub trigger_range_right: Option<[u8; 2]>,
+    /// How far a trigger has to move past its threshold to press its button, and back to release it.
+    pub trigger_hysteresis: u8,
+    /// N64 button that is pressed while both triggers are past their threshold.
+    pub both_triggers_button: Option<N64Button>,
+    /// If set, the triggers no longer press their own buttons while `both_triggers_button` is pressed.
+    pub both_triggers_exclusive: bool,
+    pub trigger_mode: TriggerMode,
+    pub trigger_axis: StickAxis,
+    /// The pak that is inserted in the controllers, with `Pak::None` to disable rumble.
+    pub pak: Pak,
+    pub all_controllers_present: bool,
+    /// Allow more than one port to read from the same GC port.
+    pub allow_shared_source_ports: bool,
+    pub merge_mode: MergeMode,
+    pub turbo_buttons: Vec<N64Button>,
+    pub turbo_rate: u8,
+    /// Number of frames that a D-pad direction is held before it is repeatedly pressed again, or 0 to not repeat it.
+    pub d_pad_repeat_delay: u16,
+    /// Number of frames between the repeated presses of a held D-pad direction.
+    pub d_pad_repeat_rate: u8,
+    pub macros: Vec<Macro>,
+    /// GC buttons that reset the ROM when they are all held for `reset_chord_frames` frames.
+    pub reset_chord: Vec<GcButton>,
+    pub reset_chord_frames: u32,
+    pub poll_mode: PollMode,
+    pub poll_interval: u64,
+    pub send_init_command: SendInitCommand,
+    /// How long the input of a newly connected adapter is ignored, in milliseconds.
+    pub adapter_startup_delay: u64,
+    /// The longest wait before reconnecting to an adapter that keeps getting lost, in milliseconds.
+    pub max_reconnect_interval: u64,
+    /// The most verbose messages that are printed, or the default for the build when not set.
+    pub log_level: Option<M64Message>,
+    /// The port (starting from 1) whose controller state is logged regularly, for tuning deadzones and thresholds.
+    pub diagnostics_port: Option<usize>,
+    /// Send the control stick values to the game as they are read, for calibration tools. For diagnostics only.
+    pub diagnostics_raw_stick: bool,
+    /// Log a warning when the adapter thread stops responding, which freezes the input.
+    pub adapter_thread_watchdog: bool,
+    /// Count how often each N64 button is pressed, and log the counts when the ROM is closed.
+    pub log_button_counts: bool,
+    /// The file that the input is recorded to while a game runs.
+    pub record_file: Option<PathBuf>,
+    /// The recording that is played back instead of the input of the controllers.
+    pub playback_file: Option<PathBuf>,
+    pub button_preset: ButtonPreset,
     pub controller_mapping: ControllerMapping,
+    /// The port (starting from 1) that the keyboard controls while no controller is connected to it.
+    pub keyboard_port: usize,
+    /// Use `KeyboardMapping::FALLBACK` while no adapter is connected, if no key is mapped in `keyboard_mapping`.
+    pub keyboard_fallback: bool,
+    pub keyboard_mapping: KeyboardMapping,
+    /// Send neutral input while the emulator window does not have focus, when the front-end calls `GCASetFocus`.
+    pub neutral_on_focus_loss: bool,
+    pub port_1: PortConfig,
+    pub port_2: PortConfig,
+    pub port_3: PortConfig,
+    pub port_4: PortConfig,
+    pub profiles: BTreeMap<String, Profile>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// The shortest allowed time between adapter reads, in microseconds.
+const MIN_POLL_INTERVAL: u64 = 100;
+/// The longest allowed `adapter_startup_delay`, in milliseconds, so that input is not ignored for too long by mistake.
+const MAX_ADAPTER_STARTUP_DELAY: u64 = 5000;
+/// The range of `max_reconnect_interval`, in milliseconds, from the first wait of the backoff to a minute.
+const RECONNECT_INTERVAL_RANGE: (u64, u64) = (200, 60_000);
+/// The stick smoothing must be below 1, which would keep the stick in place.
+const MAX_SMOOTHING: f32 = 1.0;
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            control_stick_deadzone: 20,
+            control_stick_deadzone_shape: DeadzoneShape::Radial,
+            control_stick_sensitivity: 180,
+            control_stick_scale: 1.0,
+            control_stick_response_curve: 1.0,
+            control_stick_anti_deadzone: 0.0,
+            control_stick_mode: StickMode::Analog,
+            stick_to_d_pad: StickToDPad::Off,
+            stick_to_d_pad_threshold: 40,
+            control_stick_gate: StickGate::None,
+            control_stick_gate_radius: 80.0,
+            control_stick_max: crate::stick::N64_STICK_MAX,
+            control_stick_invert_x: false,
+            control_stick_invert_y: false,
+            control_stick_smoothing: 0.0,
+            c_stick_smoothing: 0.0,
+            c_stick_deadzone: 40,
+            c_stick_threshold_low: None,
+            c_stick_threshold_high: None,
+            c_stick_threshold_x: None,
+            c_stick_threshold_y: None,
+            c_stick_hysteresis: 8,
+            c_stick_mode: CStickMode::Digital,
+            c_stick_invert_x: false,
+            c_stick_invert_y: false,
+            swap_sticks: false,
+            d_pad_drives_stick: false,
+            socd_mode: SocdMode::Raw,
+            use_origin: true,
+            calibrate_on_connect: false,
+            calibrate_key: None,
+            precision_button: None,
+            precision_button_exclusive: false,
+            precision_scale: 0.5,
+            shift_button: None,
+            shift_button_passthrough: false,
+            shift_mapping: ShiftMapping::default(),
+            extra_buttons: ExtraButtons::default(),
+            trigger_threshold: 168,
+            trigger_threshold_left: None,
+            trigger_threshold_right: None,
+            trigger_range: [0, 255],
+            trigger_range_left: None,
+            trigger_range_right: None,
+            trigger_hysteresis: 0,
+            both_triggers_button: None,
+            both_triggers_exclusive: false,
+            trigger_mode: TriggerMode::Digital,
+            trigger_axis: StickAxis::X,
+            pak: Pak::Rumble,
+            all_controllers_present: false,
+            allow_shared_source_ports: false,
+            merge_mode: MergeMode::Separate,
+            turbo_buttons: Vec::new(),
+            turbo_rate: 2,
+            d_pad_repeat_delay: 0,
+            d_pad_repeat_rate: 6,
+            macros: Vec::new(),
+            reset_chord: Vec::new(),
+            reset_chord_frames: 60,
+            poll_mode: PollMode::Blocking,
+            poll_interval: 1000,
+            send_init_command: SendInitCommand::Auto,
+            adapter_startup_delay: 0,
+            max_reconnect_interval: 5000,
+            log_level: None,
+            diagnostics_port: None,
+            diagnostics_raw_stick: false,
+            adapter_thread_watchdog: true,
+            log_button_counts: false,
+            record_file: None,
+            playback_file: None,
+            button_preset: ButtonPreset::Custom,
+            controller_mapping: ControllerMapping::default(),
+            keyboard_port: 1,
+            keyboard_fallback: true,
+            keyboard_mapping: KeyboardMapping::default(),
+            neutral_on_focus_loss: false,
+            port_1: PortConfig::default(),
+            port_2: PortConfig::default(),
+            port_3: PortConfig::default(),
+            port_4: PortConfig::default(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+/// Settings for a single port. Settings that are not set use the global value.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PortConfig {
+    /// Whether the port is used. A disabled port is not reported to the game, and its controller is ignored.
+    pub enabled: bool,
+    /// Whether the port is reported to the game when the game starts, even if no controller is connected to it.
+    pub force_present: bool,
+    pub control_stick_deadzone: Option<u8>,
+    /// How much the control stick of this port is amplified after the deadzone, with 1.0 for no change.
+    pub control_stick_gain: f32,
+    /// The adapter that this port reads from, by its serial number, or by its USB port (`usb-<bus>-<ports>`) for
+    /// adapters without one.
+    pub adapter: Option<String>,
+    /// The GC port (starting from 1) that this port reads from, instead of the port with the same number.
+    pub source_port: Option<u8>,
+    pub pak: Option<Pak>,
+    /// The profile that this port uses when the plugin starts.
+    pub profile: Option<String>,
+    /// Keyboard key that switches this port to the next profile.
+    pub profile_key: Option<i32>,
+}
+
+impl Default for PortConfig {
+    fn default() -> Self {
+        PortConfig {
+            enabled: true,
+            force_present: false,
+            control_stick_deadzone: None,
+            control_stick_gain: 1.0,
+            adapter: None,
+            source_port: None,
+            pak: None,
+            profile: None,
+            profile_key: None,
+        }
+    }
+}
+
+/// A sequence of N64 buttons that is pressed while a combination of GC buttons is held.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Macro {
+    /// The GC buttons that start the macro when they are all held.
+    pub trigger: Vec<GcButton>,
+    pub steps: Vec<MacroStep>,
+}
+
+impl Macro {
+    /// Get the bit pattern of the N64 buttons that are pressed the given number of frames after the macro started.
+    /// Nothing is pressed after the last step.
+    pub fn buttons_at(&self, frame: u64) -> u32 {
+        let mut end = 0;
+        for step in &self.steps {
+            end += u64::from(step.frames);
+            if frame < end {
+                return step
+                    .buttons
+                    .iter()
+                    .fold(0, |mask, button| mask | button.bit_pattern());
+            }
+        }
+
+        0
+    }
+}
+
+/// The N64 buttons that a macro presses for a number of frames.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MacroStep {
+    pub buttons: Vec<N64Button>,
+    pub frames: u32,
+}
+
+/// Named settings that a port can switch between, e.g. for the preferences of different players. Settings that are
+/// not set use the settings of the port.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Profile {
+    pub control_stick_deadzone: Option<u8>,
+    pub button_preset: Option<ButtonPreset>,
+    pub controller_mapping: Option<ControllerMapping>,
+}
+
+impl Profile {
+    /// Get the controller mapping of the profile, with the button preset taking precedence over the mapping.
+    pub fn controller_mapping(&self) -> Option<ControllerMapping> {
+        match self.button_preset {
+            Some(preset) => Some(preset.controller_mapping(self.controller_mapping.as_ref())),
+            None => self.controller_mapping,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
 pub struct ControllerMapping {
     pub a: N64Button,
     pub b: N64Button,
@@ -32,22 +344,240 @@ pub struct ControllerMapping {
     pub c_stick_right: N64Button,
     pub c_stick_down: N64Button,
     pub c_stick_up: N64Button,
+    /// The button that the left trigger presses when it is pressed past its threshold, before the digital click at
+    /// the end. If not set, this is the button of `l`.
+    pub l_analog: Option<N64Button>,
+    /// Like `l_analog`, for the right trigger.
+    pub r_analog: Option<N64Button>,
+}
+
+impl Default for ControllerMapping {
+    fn default() -> Self {
+        ControllerMapping::classic()
+    }
+}
+
+impl ControllerMapping {
+    /// The mapping of the original N64 controller adapters, with GC Z as N64 L and GC L as N64 Z.
+    pub fn classic() -> Self {
+        ControllerMapping {
+            a: N64Button::A,
+            b: N64Button::B,
+            x: N64Button::CRight,
+            y: N64Button::CLeft,
+            start: N64Button::Start,
+            z: N64Button::L,
+            l: N64Button::Z,
+            r: N64Button::R,
+            d_pad_left: N64Button::DPadLeft,
+            d_pad_right: N64Button::DPadRight,
+            d_pad_down: N64Button::DPadDown,
+            d_pad_up: N64Button::DPadUp,
+            c_stick_left: N64Button::CLeft,
+            c_stick_right: N64Button::CRight,
+            c_stick_down: N64Button::CDown,
+            c_stick_up: N64Button::CUp,
+            l_analog: None,
+            r_analog: None,
+        }
+    }
+
+    /// The mapping where every button is the N64 button with the same label: L is L, R is R and Z is Z.
+    pub fn natural() -> Self {
+        ControllerMapping {
+            z: N64Button::Z,
+            l: N64Button::L,
+            ..ControllerMapping::classic()
+        }
+    }
+
+    /// The classic mapping with GC Z as N64 Start, for pausing without reaching for Start.
+    pub fn pause() -> Self {
+        ControllerMapping {
+            z: N64Button::Start,
+            ..ControllerMapping::classic()
+        }
+    }
+}
+
+/// The alternate N64 buttons of the GC buttons while the shift button is held. Buttons that are not set keep their
+/// usual mapping.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShiftMapping {
+    pub a: Option<N64Button>,
+    pub b: Option<N64Button>,
+    pub x: Option<N64Button>,
+    pub y: Option<N64Button>,
+    pub start: Option<N64Button>,
+    pub z: Option<N64Button>,
+    pub l: Option<N64Button>,
+    pub r: Option<N64Button>,
+    pub d_pad_left: Option<N64Button>,
+    pub d_pad_right: Option<N64Button>,
+    pub d_pad_down: Option<N64Button>,
+    pub d_pad_up: Option<N64Button>,
+}
+
+impl ShiftMapping {
+    /// Get the alternate N64 button of the given GC button.
+    pub fn get(&self, button: GcButton) -> Option<N64Button> {
+        match button {
+            GcButton::A => self.a,
+            GcButton::B => self.b,
+            GcButton::X => self.x,
+            GcButton::Y => self.y,
+            GcButton::Start => self.start,
+            GcButton::Z => self.z,
+            GcButton::L => self.l,
+            GcButton::R => self.r,
+            GcButton::DPadLeft => self.d_pad_left,
+            GcButton::DPadRight => self.d_pad_right,
+            GcButton::DPadDown => self.d_pad_down,
+            GcButton::DPadUp => self.d_pad_up,
+        }
+    }
+}
+
+/// N64 buttons that each GC button presses in addition to its mapped button, e.g. Start on A for title screens that
+/// only accept Start.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExtraButtons {
+    pub a: Vec<N64Button>,
+    pub b: Vec<N64Button>,
+    pub x: Vec<N64Button>,
+    pub y: Vec<N64Button>,
+    pub start: Vec<N64Button>,
+    pub z: Vec<N64Button>,
+    pub l: Vec<N64Button>,
+    pub r: Vec<N64Button>,
+    pub d_pad_left: Vec<N64Button>,
+    pub d_pad_right: Vec<N64Button>,
+    pub d_pad_down: Vec<N64Button>,
+    pub d_pad_up: Vec<N64Button>,
+}
+
+impl ExtraButtons {
+    /// Get the extra N64 buttons of the given GC button.
+    pub fn get(&self, button: GcButton) -> &[N64Button] {
+        match button {
+            GcButton::A => &self.a,
+            GcButton::B => &self.b,
+            GcButton::X => &self.x,
+            GcButton::Y => &self.y,
+            GcButton::Start => &self.start,
+            GcButton::Z => &self.z,
+            GcButton::L => &self.l,
+            GcButton::R => &self.r,
+            GcButton::DPadLeft => &self.d_pad_left,
+            GcButton::DPadRight => &self.d_pad_right,
+            GcButton::DPadDown => &self.d_pad_down,
+            GcButton::DPadUp => &self.d_pad_up,
+        }
+    }
+}
+
+/// The keyboard keys for each N64 input, using the key codes from the emulator.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct KeyboardMapping {
+    pub a: Option<i32>,
+    pub b: Option<i32>,
+    pub start: Option<i32>,
+    pub z: Option<i32>,
+    pub l: Option<i32>,
+    pub r: Option<i32>,
+    pub d_pad_left: Option<i32>,
+    pub d_pad_right: Option<i32>,
+    pub d_pad_down: Option<i32>,
+    pub d_pad_up: Option<i32>,
+    pub c_left: Option<i32>,
+    pub c_right: Option<i32>,
+    pub c_down: Option<i32>,
+    pub c_up: Option<i32>,
+    pub stick_left: Option<i32>,
+    pub stick_right: Option<i32>,
+    pub stick_down: Option<i32>,
+    pub stick_up: Option<i32>,
+}
+
+impl KeyboardMapping {
+    /// The keys that are used while no adapter is connected with `keyboard_fallback`, so that the game can still be
+    /// played: the stick on WASD, A and B on X and Z, the C-buttons on TFGH and the D-pad on IJKL.
+    pub const FALLBACK: KeyboardMapping = KeyboardMapping {
+        a: Some(120),
+        b: Some(122),
+        start: Some(13),
+        z: Some(32),
+        l: Some(113),
+        r: Some(101),
+        d_pad_left: Some(106),
+        d_pad_right: Some(108),
+        d_pad_down: Some(107),
+        d_pad_up: Some(105),
+        c_left: Some(102),
+        c_right: Some(104),
+        c_down: Some(103),
+        c_up: Some(116),
+        stick_left: Some(97),
+        stick_right: Some(100),
+        stick_down: Some(115),
+        stick_up: Some(119),
+    };
+
+    /// Check if any key is mapped.
+    pub fn any_mapped(&self) -> bool {
+        self.buttons().iter().any(|(key, _)| key.is_some())
+            || [
+                self.stick_left,
+                self.stick_right,
+                self.stick_down,
+                self.stick_up,
+            ]
+            .iter()
+            .any(Option::is_some)
+    }
+
+    /// Get the key for each N64 button.
+    pub fn buttons(&self) -> [(Option<i32>, N64Button); 14] {
+        [
+            (self.a, N64Button::A),
+            (self.b, N64Button::B),
+            (self.start, N64Button::Start),
+            (self.z, N64Button::Z),
+            (self.l, N64Button::L),
+            (self.r, N64Button::R),
+            (self.d_pad_left, N64Button::DPadLeft),
+            (self.d_pad_right, N64Button::DPadRight),
+            (self.d_pad_down, N64Button::DPadDown),
+            (self.d_pad_up, N64Button::DPadUp),
+            (self.c_left, N64Button::CLeft),
+            (self.c_right, N64Button::CRight),
+            (self.c_down, N64Button::CDown),
+            (self.c_up, N64Button::CUp),
+        ]
+    }
 }
 
 impl Config {
+    /// Read the configuration from the given file. Missing entries fall back to their defaults.
     pub fn read_from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
         let path = path.as_ref();
         let mut file = File::open(path)?;
         let mut string = String::new();
         file.read_to_string(&mut string)?;
-        let cfg = toml::from_str(&string).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let mut cfg: Config = toml::from_str(&string).map_err(io::Error::other)?;
+        cfg.validate();
 
         Ok(cfg)
     }
 
+    /// Write the default configuration to the given file. If the file could not be written, the default
+    /// configuration is returned as an error.
+    #[allow(clippy::result_large_err)]
     pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Self> {
-        let contents = include_str!("../mupen64plus-input-gca.toml");
-        let cfg = toml::from_str(contents).unwrap();
+        let cfg = toml::from_str(DEFAULT_CONFIG).unwrap();
 
         let path = path.as_ref();
         let mut file = match File::create(path) {
@@ -55,14 +585,708 @@ impl Config {
             Err(_) => return Err(cfg),
         };
 
-        match file.write_all(contents.as_bytes()) {
+        match file.write_all(DEFAULT_CONFIG.as_bytes()) {
             Ok(_) => Ok(cfg),
             Err(_) => Err(cfg),
         }
     }
+
+    /// Get the settings for the given port.
+    pub fn port<T>(&self, channel: T) -> &PortConfig
+    where
+        T: TryInto<Channel>,
+        <T as TryInto<Channel>>::Error: Debug,
+    {
+        match channel.try_into().unwrap() {
+            Channel::One => &self.port_1,
+            Channel::Two => &self.port_2,
+            Channel::Three => &self.port_3,
+            Channel::Four => &self.port_4,
+        }
+    }
+
+    /// Get the control stick deadzone for the given port.
+    pub fn control_stick_deadzone<T>(&self, channel: T) -> u8
+    where
+        T: TryInto<Channel>,
+        <T as TryInto<Channel>>::Error: Debug,
+    {
+        self.port(channel)
+            .control_stick_deadzone
+            .unwrap_or(self.control_stick_deadzone)
+    }
+
+    /// Get the controller mapping of the button preset.
+    pub fn controller_mapping(&self) -> ControllerMapping {
+        self.button_preset
+            .controller_mapping(Some(&self.controller_mapping))
+    }
+
+    /// Get the profile (starting from 1, in the order of the names) and its name, or `None` for 0.
+    pub fn profile(&self, index: usize) -> Option<(&str, &Profile)> {
+        let (name, profile) = self.profiles.iter().nth(index.checked_sub(1)?)?;
+        Some((name, profile))
+    }
+
+    /// Get the index of the profile that the given port uses when the plugin starts, or 0 if it uses none.
+    pub fn initial_profile<T>(&self, channel: T) -> usize
+    where
+        T: TryInto<Channel>,
+        <T as TryInto<Channel>>::Error: Debug,
+    {
+        self.port(channel)
+            .profile
+            .as_ref()
+            .and_then(|name| self.profiles.keys().position(|n| n == name))
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Get the pak that is inserted in the controller of the given port.
+    pub fn pak<T>(&self, channel: T) -> Pak
+    where
+        T: TryInto<Channel>,
+        <T as TryInto<Channel>>::Error: Debug,
+    {
+        self.port(channel).pak.unwrap_or(self.pak)
+    }
+
+    /// Get the ID of the adapter that each port is pinned to.
+    pub fn pinned_adapters(&self) -> [Option<&str>; 4] {
+        [&self.port_1, &self.port_2, &self.port_3, &self.port_4].map(|p| p.adapter.as_deref())
+    }
+
+    /// Get the GC port that each port reads from.
+    pub fn source_ports(&self) -> [Channel; 4] {
+        let ports = [&self.port_1, &self.port_2, &self.port_3, &self.port_4];
+        let mut sources = [Channel::One, Channel::Two, Channel::Three, Channel::Four];
+
+        for (source, port) in sources.iter_mut().zip(ports) {
+            if let Some(p) = port.source_port {
+                *source = (p as usize - 1).try_into().unwrap();
+            }
+        }
+
+        sources
+    }
+
+    /// Get the time that the input of a newly connected adapter is ignored.
+    pub fn adapter_startup_delay(&self) -> Duration {
+        Duration::from_millis(self.adapter_startup_delay)
+    }
+
+    pub fn max_reconnect_interval(&self) -> Duration {
+        Duration::from_millis(self.max_reconnect_interval)
+    }
+
+    /// Get the low and high C-stick thresholds of the X and Y axis, falling back to `c_stick_threshold_low` and
+    /// `c_stick_threshold_high`, and then to thresholds derived from `c_stick_deadzone`.
+    pub fn c_stick_thresholds(&self) -> ((u8, u8), (u8, u8)) {
+        let low = self
+            .c_stick_threshold_low
+            .unwrap_or_else(|| 128u8.saturating_sub(self.c_stick_deadzone));
+        let high = self
+            .c_stick_threshold_high
+            .unwrap_or_else(|| 128u8.saturating_add(self.c_stick_deadzone));
+        let axis = |thresholds: Option<[u8; 2]>| thresholds.map_or((low, high), |[l, h]| (l, h));
+
+        (
+            axis(self.c_stick_threshold_x),
+            axis(self.c_stick_threshold_y),
+        )
+    }
+
+    /// Get the thresholds for the left and right triggers, falling back to `trigger_threshold`.
+    pub fn trigger_thresholds(&self) -> (u8, u8) {
+        (
+            self.trigger_threshold_left
+                .unwrap_or(self.trigger_threshold),
+            self.trigger_threshold_right
+                .unwrap_or(self.trigger_threshold),
+        )
+    }
+
+    /// Get the range that the left and right trigger reach, falling back to `trigger_range`.
+    pub fn trigger_ranges(&self) -> ([u8; 2], [u8; 2]) {
+        (
+            self.trigger_range_left.unwrap_or(self.trigger_range),
+            self.trigger_range_right.unwrap_or(self.trigger_range),
+        )
+    }
+
+    /// The time to sleep between adapter reads.
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_micros(self.poll_interval)
+    }
+
+    /// Get the bit pattern of all turbo buttons.
+    pub fn turbo_mask(&self) -> u32 {
+        self.turbo_buttons
+            .iter()
+            .fold(0, |mask, button| mask | button.bit_pattern())
+    }
+
+    /// Clamp out-of-range values, logging a warning for each value that was changed.
+    fn validate(&mut self) {
+        const MAX_STICK_DEADZONE: u8 = i8::MAX as u8;
+
+        if self.control_stick_deadzone > MAX_STICK_DEADZONE {
+            debug_print!(
+                M64Message::Warning,
+                "control_stick_deadzone {} is out of range, using {}",
+                self.control_stick_deadzone,
+                MAX_STICK_DEADZONE
+            );
+            self.control_stick_deadzone = MAX_STICK_DEADZONE;
+        }
+
+        // A larger deadzone would put the low threshold at 0, so that a centered C-stick reads as pressed
+        if self.c_stick_deadzone > MAX_STICK_DEADZONE {
+            debug_print!(
+                M64Message::Warning,
+                "c_stick_deadzone {} is out of range, using {}",
+                self.c_stick_deadzone,
+                MAX_STICK_DEADZONE
+            );
+            self.c_stick_deadzone = MAX_STICK_DEADZONE;
+        }
+
+        if !(self.control_stick_scale.is_finite() && self.control_stick_scale >= 0.0) {
+            debug_print!(
+                M64Message::Warning,
+                "control_stick_scale {} is invalid, using 1.0",
+                self.control_stick_scale
+            );
+            self.control_stick_scale = 1.0;
+        }
+
+        if !(self.control_stick_response_curve.is_finite()
+            && self.control_stick_response_curve > 0.0)
+        {
+            debug_print!(
+                M64Message::Warning,
+                "control_stick_response_curve {} is invalid, using 1.0",
+                self.control_stick_response_curve
+            );
+            self.control_stick_response_curve = 1.0;
+        }
+
+        // With more hysteresis, a C-stick moved from one threshold to the other would keep the first direction
+        let (c_x, c_y) = self.c_stick_thresholds();
+        let too_large = |(low, high): (u8, u8)| {
+            high > low && self.c_stick_hysteresis as u16 * 2 >= (high - low) as u16
+        };
+        if too_large(c_x) || too_large(c_y) {
+            debug_print!(
+                M64Message::Warning,
+                "c_stick_hysteresis {} is too large for the C-stick thresholds, using 0",
+                self.c_stick_hysteresis
+            );
+            self.c_stick_hysteresis = 0;
+        }
+
+        // Otherwise a trigger at the end of its range would never press its button
+        let (left, right) = self.trigger_thresholds();
+        if left.max(right) as u16 + self.trigger_hysteresis as u16 >= u8::MAX as u16 {
+            debug_print!(
+                M64Message::Warning,
+                "trigger_hysteresis {} is too large for the trigger thresholds, using 0",
+                self.trigger_hysteresis
+            );
+            self.trigger_hysteresis = 0;
+        }
+
+        for (name, smoothing) in [
+            ("control_stick_smoothing", &mut self.control_stick_smoothing),
+            ("c_stick_smoothing", &mut self.c_stick_smoothing),
+        ] {
+            if !(0.0..MAX_SMOOTHING).contains(smoothing) {
+                debug_print!(
+                    M64Message::Warning,
+                    "{} {} is invalid, using 0.0",
+                    name,
+                    smoothing
+                );
+                *smoothing = 0.0;
+            }
+        }
+
+        let max_anti_deadzone = crate::stick::N64_STICK_MAX as f32;
+        if !(0.0..=max_anti_deadzone).contains(&self.control_stick_anti_deadzone) {
+            debug_print!(
+                M64Message::Warning,
+                "control_stick_anti_deadzone {} is invalid, using 0.0",
+                self.control_stick_anti_deadzone
+            );
+            self.control_stick_anti_deadzone = 0.0;
+        }
+
+        if !(self.control_stick_gate_radius.is_finite() && self.control_stick_gate_radius >= 1.0) {
+            debug_print!(
+                M64Message::Warning,
+                "control_stick_gate_radius {} is invalid, using 80.0",
+                self.control_stick_gate_radius
+            );
+            self.control_stick_gate_radius = 80.0;
+        }
+
+        if !(1..=crate::stick::N64_STICK_MAX).contains(&self.control_stick_max) {
+            debug_print!(
+                M64Message::Warning,
+                "control_stick_max {} is not from 1 to {}, using {}",
+                self.control_stick_max,
+                crate::stick::N64_STICK_MAX,
+                crate::stick::N64_STICK_MAX
+            );
+            self.control_stick_max = crate::stick::N64_STICK_MAX;
+        }
+
+        if !(self.precision_scale.is_finite() && self.precision_scale >= 0.0) {
+            debug_print!(
+                M64Message::Warning,
+                "precision_scale {} is invalid, using 0.5",
+                self.precision_scale
+            );
+            self.precision_scale = 0.5;
+        }
+
+        for (name, range) in [
+            ("trigger_range", Some(&mut self.trigger_range)),
+            ("trigger_range_left", self.trigger_range_left.as_mut()),
+            ("trigger_range_right", self.trigger_range_right.as_mut()),
+        ] {
+            if let Some(range) = range.filter(|[min, max]| min >= max) {
+                debug_print!(
+                    M64Message::Warning,
+                    "{} {:?} must have a minimum below the maximum, using [0, 255]",
+                    name,
+                    range
+                );
+                *range = [0, 255];
+            }
+        }
+
+        if self.turbo_rate == 0 {
+            debug_print!(
+                M64Message::Warning,
+                "turbo_rate must be 1 or higher, using 1"
+            );
+            self.turbo_rate = 1;
+        }
+
+        // The direction is released for a frame in every repeat, so it also needs a frame to be pressed
+        if self.d_pad_repeat_rate < 2 {
+            debug_print!(
+                M64Message::Warning,
+                "d_pad_repeat_rate must be 2 or higher, using 2"
+            );
+            self.d_pad_repeat_rate = 2;
+        }
+
+        let macros = self.macros.len();
+        self.macros.retain(|m| !m.trigger.is_empty());
+        if self.macros.len() < macros {
+            debug_print!(
+                M64Message::Warning,
+                "Ignoring {} macro(s) without trigger buttons",
+                macros - self.macros.len()
+            );
+        }
+
+        if !(1..=4).contains(&self.keyboard_port) {
+            debug_print!(
+                M64Message::Warning,
+                "keyboard_port {} is not a port from 1 to 4, using 1",
+                self.keyboard_port
+            );
+            self.keyboard_port = 1;
+        }
+
+        if self.diagnostics_raw_stick {
+            debug_print!(
+                M64Message::Warning,
+                "diagnostics_raw_stick is enabled, so the raw control stick values are sent to the game"
+            );
+        }
+
+        if let Some(port) = self.diagnostics_port.filter(|p| !(1..=4).contains(p)) {
+            debug_print!(
+                M64Message::Warning,
+                "diagnostics_port {} is not a port from 1 to 4, ignoring it",
+                port
+            );
+            self.diagnostics_port = None;
+        }
+
+        if self.poll_interval < MIN_POLL_INTERVAL {
+            debug_print!(
+                M64Message::Warning,
+                "poll_interval must be {} or higher, using {}",
+                MIN_POLL_INTERVAL,
+                MIN_POLL_INTERVAL
+            );
+            self.poll_interval = MIN_POLL_INTERVAL;
+        }
+
+        let max_stick = crate::stick::N64_STICK_MAX as u8;
+        if self.stick_to_d_pad_threshold > max_stick {
+            debug_print!(
+                M64Message::Warning,
+                "stick_to_d_pad_threshold {} is out of range, using {}",
+                self.stick_to_d_pad_threshold,
+                max_stick
+            );
+            self.stick_to_d_pad_threshold = max_stick;
+        }
+
+        if self.adapter_startup_delay > MAX_ADAPTER_STARTUP_DELAY {
+            debug_print!(
+                M64Message::Warning,
+                "adapter_startup_delay {} is out of range, using {}",
+                self.adapter_startup_delay,
+                MAX_ADAPTER_STARTUP_DELAY
+            );
+            self.adapter_startup_delay = MAX_ADAPTER_STARTUP_DELAY;
+        }
+
+        let (min, max) = RECONNECT_INTERVAL_RANGE;
+        if !(min..=max).contains(&self.max_reconnect_interval) {
+            let clamped = self.max_reconnect_interval.clamp(min, max);
+            debug_print!(
+                M64Message::Warning,
+                "max_reconnect_interval {} is out of range, using {}",
+                self.max_reconnect_interval,
+                clamped
+            );
+            self.max_reconnect_interval = clamped;
+        }
+
+        for (i, port) in [
+            &mut self.port_1,
+            &mut self.port_2,
+            &mut self.port_3,
+            &mut self.port_4,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if let Some(deadzone) = port
+                .control_stick_deadzone
+                .filter(|&d| d > MAX_STICK_DEADZONE)
+            {
+                debug_print!(
+                    M64Message::Warning,
+                    "port_{}.control_stick_deadzone {} is out of range, using {}",
+                    i + 1,
+                    deadzone,
+                    MAX_STICK_DEADZONE
+                );
+                port.control_stick_deadzone = Some(MAX_STICK_DEADZONE);
+            }
+
+            if !(port.control_stick_gain.is_finite() && port.control_stick_gain > 0.0) {
+                debug_print!(
+                    M64Message::Warning,
+                    "port_{}.control_stick_gain {} is invalid, using 1.0",
+                    i + 1,
+                    port.control_stick_gain
+                );
+                port.control_stick_gain = 1.0;
+            }
+
+            if port.adapter.as_deref() == Some("") {
+                debug_print!(
+                    M64Message::Warning,
+                    "port_{}.adapter is empty, ignoring it",
+                    i + 1
+                );
+                port.adapter = None;
+            }
+
+            if let Some(source_port) = port.source_port.filter(|p| !(1..=4).contains(p)) {
+                debug_print!(
+                    M64Message::Warning,
+                    "port_{}.source_port {} must be from 1 to 4, ignoring it",
+                    i + 1,
+                    source_port
+                );
+                port.source_port = None;
+            }
+
+            if let Some(name) = port
+                .profile
+                .take_if(|name| !self.profiles.contains_key(name.as_str()))
+            {
+                debug_print!(
+                    M64Message::Warning,
+                    "port_{}.profile {:?} is not defined in [profiles], ignoring it",
+                    i + 1,
+                    name
+                );
+            }
+        }
+
+        for (name, profile) in &mut self.profiles {
+            if let Some(deadzone) = profile
+                .control_stick_deadzone
+                .filter(|&d| d > MAX_STICK_DEADZONE)
+            {
+                debug_print!(
+                    M64Message::Warning,
+                    "profiles.{}.control_stick_deadzone {} is out of range, using {}",
+                    name,
+                    deadzone,
+                    MAX_STICK_DEADZONE
+                );
+                profile.control_stick_deadzone = Some(MAX_STICK_DEADZONE);
+            }
+        }
+
+        let sources = self.source_ports();
+        let shared = (0..4).any(|i| sources[..i].contains(&sources[i]));
+        if shared && !self.allow_shared_source_ports {
+            debug_print!(
+                M64Message::Warning,
+                "More than one port reads from the same GC port, ignoring source_port (set it for each \
+                port that is remapped, or set allow_shared_source_ports)"
+            );
+            for port in [
+                &mut self.port_1,
+                &mut self.port_2,
+                &mut self.port_3,
+                &mut self.port_4,
+            ] {
+                port.source_port = None;
+            }
+        }
+    }
+}
+
+/// A digital button on the GameCube controller. L and R are the digital press at the end of the trigger.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GcButton {
+    A,
+    B,
+    X,
+    Y,
+    Start,
+    Z,
+    L,
+    R,
+    DPadLeft,
+    DPadRight,
+    DPadDown,
+    DPadUp,
+}
+
+impl GcButton {
+    pub const ALL: [GcButton; 12] = [
+        GcButton::A,
+        GcButton::B,
+        GcButton::X,
+        GcButton::Y,
+        GcButton::Start,
+        GcButton::Z,
+        GcButton::L,
+        GcButton::R,
+        GcButton::DPadLeft,
+        GcButton::DPadRight,
+        GcButton::DPadDown,
+        GcButton::DPadUp,
+    ];
+}
+
+/// Watches the configuration file for changes, using its modification time.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let modified = modified_time(&path);
+
+        ConfigWatcher { path, modified }
+    }
+
+    /// Read the configuration again if the file was modified since it was last read.
+    pub fn reload_if_changed(&mut self) -> Option<io::Result<Config>> {
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+
+        self.modified = modified;
+        Some(Config::read_from_file(&self.path))
+    }
+
+    /// Read the configuration again, even if the file was not modified.
+    pub fn reload(&mut self) -> io::Result<Config> {
+        self.modified = modified_time(&self.path);
+        Config::read_from_file(&self.path)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// A pak that is inserted in the N64 controller.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pak {
+    None,
+    /// A controller pak, which games use for saves. The core stores its contents in a `.mpk` file.
+    #[serde(alias = "Mempak")]
+    Memory,
+    Rumble,
+}
+
+/// How the adapter thread waits for new input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PollMode {
+    /// Sleep for `poll_interval` between reads.
+    Sleep,
+    /// Read again as soon as the previous read returns, so the thread wakes when the adapter sends new input.
+    Blocking,
+    /// Read in `GetKeys` instead of on the adapter thread, so that the input is as recent as possible. `GetKeys`
+    /// waits for the adapter to send new input.
+    Direct,
+}
+
+/// Whether the init command, which starts the input reports, is sent to the adapter.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SendInitCommand {
+    /// Send it, but keep using the adapter if it does not accept the command, and open the adapter again without it
+    /// if the adapter sends no input after it.
+    Auto,
+    /// Send it, and give up on the adapter if it does not accept the command.
+    Yes,
+    /// Don't send it, for adapters that start sending input by themselves.
+    No,
+}
+
+/// How the controllers on the same port of different adapters are used.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeMode {
+    /// The controller of the first adapter is used, and the others control the ports that are free.
+    Separate,
+    /// The controller of the first adapter is used, and the others are ignored.
+    FirstWins,
+    /// The controller of the last adapter is used, and the others are ignored.
+    LastWins,
+    /// The controllers are combined: a button is pressed if it is pressed on any of them, and the stick, C-stick and
+    /// triggers that are moved the furthest are used.
+    Or,
+}
+
+/// How opposing D-pad directions that are held at the same time are resolved.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SocdMode {
+    /// Press both directions.
+    Raw,
+    /// Press neither direction.
+    Neutral,
+    /// Press the direction that was pressed last.
+    LastWins,
+}
+
+/// A preset for the controller mapping.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ButtonPreset {
+    /// The mapping in the `[controller_mapping]` section.
+    Custom,
+    /// See `ControllerMapping::classic`.
+    Classic,
+    /// See `ControllerMapping::natural`.
+    Natural,
+    /// See `ControllerMapping::pause`.
+    Pause,
+}
+
+impl ButtonPreset {
+    /// Get the mapping of the preset, where `custom` is the mapping of `ButtonPreset::Custom`.
+    fn controller_mapping(self, custom: Option<&ControllerMapping>) -> ControllerMapping {
+        match self {
+            ButtonPreset::Custom => custom.copied().unwrap_or_default(),
+            ButtonPreset::Classic => ControllerMapping::classic(),
+            ButtonPreset::Natural => ControllerMapping::natural(),
+            ButtonPreset::Pause => ControllerMapping::pause(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// How the analog triggers are used.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerMode {
+    /// The triggers only press the buttons they are mapped to.
+    Digital,
+    /// The triggers also move an axis of the N64 stick, depending on how far they are pressed.
+    Analog,
+}
+
+/// An axis of the N64 stick.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StickAxis {
+    X,
+    Y,
+}
+
+/// How the C-stick presses the C-buttons.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CStickMode {
+    /// A C-button is held while the C-stick is past its threshold.
+    Digital,
+    /// A C-button is pressed on a share of the reads that grows with how far the C-stick is past its threshold, for
+    /// an analog camera in games that turn the camera with the C-buttons.
+    AnalogCamera,
+}
+
+/// How the direction of the control stick is sent to the game.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StickMode {
+    /// The stick is analog, like the N64 stick.
+    Analog,
+    /// The stick is snapped to the nearest axis, fully deflected.
+    FourWay,
+    /// The stick is snapped to the nearest axis or diagonal, fully deflected.
+    EightWay,
+}
+
+/// Whether the control stick presses the N64 D-pad.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StickToDPad {
+    /// The control stick only moves the N64 stick.
+    Off,
+    /// The control stick moves the N64 stick and presses the D-pad.
+    Add,
+    /// The control stick presses the D-pad, and the N64 stick stays at the center.
+    Replace,
+}
+
+/// The gate that the control stick is limited to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StickGate {
+    /// The stick is only limited to the N64 stick range.
+    None,
+    /// An octagon like the gate of the N64 stick, with notches on the axes and diagonals.
+    Octagon,
+}
+
+/// The shape of the control stick deadzone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeadzoneShape {
+    /// A circle around the center. Movement is equally smooth in all directions.
+    Radial,
+    /// Each axis has its own deadzone, so an axis reads zero while it is within the deadzone. This stops small
+    /// diagonal movement, but makes diagonals harder to reach close to the center.
+    Axial,
+    /// A circle around the center, where the stick also snaps to an axis while the other axis is within the
+    /// deadzone. This stops small diagonal movement while keeping the full stick range.
+    Cross,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum N64Button {
     A,
     B,
@@ -80,8 +1304,49 @@ pub enum N64Button {
     CUp,
 }
 
+// Every button has its own bit, and every used bit has a button, so that no mapping can press the wrong button
+const _: () = {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < N64Button::ALL.len() {
+        let bit = N64Button::ALL[i].bit_pattern();
+        assert!(
+            bit.is_power_of_two(),
+            "N64 button bit pattern is not a single bit"
+        );
+        assert!(mask & bit == 0, "two N64 buttons have the same bit");
+        mask |= bit;
+        i += 1;
+    }
+    assert!(
+        mask == N64Button::USED_BITS,
+        "N64 button bits are not complete"
+    );
+};
+
 impl N64Button {
-    pub fn bit_pattern(&self) -> u32 {
+    pub const ALL: [N64Button; 14] = [
+        N64Button::A,
+        N64Button::B,
+        N64Button::Start,
+        N64Button::Z,
+        N64Button::L,
+        N64Button::R,
+        N64Button::DPadLeft,
+        N64Button::DPadRight,
+        N64Button::DPadDown,
+        N64Button::DPadUp,
+        N64Button::CLeft,
+        N64Button::CRight,
+        N64Button::CDown,
+        N64Button::CUp,
+    ];
+
+    /// The bits of `bit_pattern` that are used by a button. The two remaining bits of the lower two bytes of `BUTTONS`
+    /// are reserved.
+    pub const USED_BITS: u32 = 0x3FFF;
+
+    pub const fn bit_pattern(&self) -> u32 {
         match self {
             N64Button::A => 0x0080,
             N64Button::B => 0x0040,
@@ -99,4 +1364,215 @@ impl N64Button {
             N64Button::CUp => 0x0800,
         }
     }
+
+    /// Get the button with the given bit (0 to 15) of the bit pattern, if there is one.
+    pub fn from_bit(bit: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|b| b.bit_pattern() == 1 << bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_config_matches_defaults() {
+        let cfg: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        assert_eq!(cfg, Config::default());
+    }
+
+    #[test]
+    fn missing_entries_use_defaults() {
+        let cfg: Config = toml::from_str(
+            r#"
+            control_stick_deadzone = 60
+
+            [controller_mapping]
+            a = 'B'
+            b = 'A'
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(cfg.control_stick_deadzone, 60);
+        assert_eq!(cfg.c_stick_deadzone, Config::default().c_stick_deadzone);
+        assert_eq!(cfg.controller_mapping.a, N64Button::B);
+        assert_eq!(cfg.controller_mapping.b, N64Button::A);
+        assert_eq!(cfg.controller_mapping.start, N64Button::Start);
+    }
+
+    #[test]
+    fn port_deadzone_overrides_global() {
+        let mut cfg: Config = toml::from_str(
+            r#"
+            control_stick_deadzone = 30
+
+            [port_2]
+            control_stick_deadzone = 200
+            "#,
+        )
+        .unwrap();
+        cfg.validate();
+
+        assert_eq!(cfg.control_stick_deadzone(0), 30);
+        assert_eq!(cfg.control_stick_deadzone(1), 127);
+    }
+
+    #[test]
+    fn port_pak_overrides_global() {
+        let cfg: Config = toml::from_str(
+            r#"
+            pak = 'None'
+
+            [port_2]
+            pak = 'Memory'
+
+            [port_3]
+            pak = 'Rumble'
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.pak(0), Pak::None);
+        assert_eq!(cfg.pak(1), Pak::Memory);
+        assert_eq!(cfg.pak(2), Pak::Rumble);
+
+        let cfg: Config = toml::from_str("pak = 'Mempak'").unwrap();
+        assert_eq!(cfg.pak(3), Pak::Memory);
+    }
+
+    #[test]
+    fn ports_enabled_by_default() {
+        let cfg: Config = toml::from_str(
+            r#"
+            [port_2]
+            enabled = false
+
+            [port_3]
+            pak = 'Memory'
+            "#,
+        )
+        .unwrap();
+        assert!(cfg.port(0).enabled);
+        assert!(!cfg.port(1).enabled);
+        assert!(cfg.port(2).enabled);
+    }
+
+    #[test]
+    fn source_ports() {
+        use Channel::*;
+
+        let mut cfg: Config = toml::from_str(
+            r#"
+            [port_1]
+            source_port = 3
+            [port_3]
+            source_port = 1
+            "#,
+        )
+        .unwrap();
+        cfg.validate();
+        assert_eq!(cfg.source_ports(), [Three, Two, One, Four]);
+
+        let mut cfg: Config = toml::from_str(
+            r#"
+            [port_1]
+            source_port = 3
+            "#,
+        )
+        .unwrap();
+        cfg.validate();
+        assert_eq!(cfg.source_ports(), [One, Two, Three, Four]);
+
+        cfg.port_1.source_port = Some(3);
+        cfg.allow_shared_source_ports = true;
+        cfg.validate();
+        assert_eq!(cfg.source_ports(), [Three, Two, Three, Four]);
+    }
+
+    #[test]
+    fn port_profiles() {
+        let mut cfg: Config = toml::from_str(
+            r#"
+            [port_1]
+            profile = 'Tournament'
+            [port_2]
+            profile = 'Missing'
+            [profiles.Tournament]
+            button_preset = 'Natural'
+            [profiles.Casual]
+            control_stick_deadzone = 50
+            "#,
+        )
+        .unwrap();
+        cfg.validate();
+
+        assert_eq!(cfg.initial_profile(0), 2);
+        assert_eq!(cfg.initial_profile(1), 0);
+        let (name, profile) = cfg.profile(1).unwrap();
+        assert_eq!(name, "Casual");
+        assert_eq!(profile.control_stick_deadzone, Some(50));
+        assert_eq!(profile.controller_mapping(), None);
+        assert_eq!(
+            cfg.profile(2).unwrap().1.controller_mapping(),
+            Some(ControllerMapping::natural())
+        );
+        assert!(cfg.profile(0).is_none());
+        assert!(cfg.profile(3).is_none());
+    }
+
+    #[test]
+    fn c_stick_thresholds() {
+        assert_eq!(
+            Config::default().c_stick_thresholds(),
+            ((88, 168), (88, 168))
+        );
+
+        let cfg: Config = toml::from_str("c_stick_threshold_low = 70").unwrap();
+        assert_eq!(cfg.c_stick_thresholds(), ((70, 168), (70, 168)));
+
+        let cfg: Config =
+            toml::from_str("c_stick_threshold_low = 70\nc_stick_threshold_y = [100, 150]").unwrap();
+        assert_eq!(cfg.c_stick_thresholds(), ((70, 168), (100, 150)));
+
+        let mut cfg: Config = toml::from_str("c_stick_deadzone = 200").unwrap();
+        cfg.validate();
+        assert_eq!(cfg.c_stick_thresholds(), ((1, 255), (1, 255)));
+    }
+
+    #[test]
+    fn n64_button_from_bit() {
+        for button in N64Button::ALL {
+            let bit = button.bit_pattern().trailing_zeros();
+            assert_eq!(N64Button::from_bit(bit), Some(button));
+        }
+        assert_eq!(N64Button::from_bit(14), None);
+    }
+
+    #[test]
+    fn reloads_changed_config() {
+        let path = std::env::temp_dir().join(format!(
+            "mupen64plus-input-gca-reload-test-{}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, "control_stick_deadzone = 30").unwrap();
+
+        let mut watcher = ConfigWatcher::new(&path);
+        assert!(watcher.reload_if_changed().is_none());
+
+        fs::write(&path, "control_stick_deadzone = 40").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        let cfg = watcher.reload_if_changed().unwrap().unwrap();
+        assert_eq!(cfg.control_stick_deadzone, 40);
+        assert!(watcher.reload_if_changed().is_none());
+        assert_eq!(watcher.reload().unwrap().control_stick_deadzone, 40);
+
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,102 @@
+//! Functions of the Mupen64Plus core that the plugin uses.
+
+use crate::ffi::*;
+use std::{
+    ffi::{c_void, CStr},
+    mem::ManuallyDrop,
+    os::raw::{c_char, c_int},
+    ptr,
+    sync::{PoisonError, RwLock},
+};
+
+#[cfg(unix)]
+use libloading::os::unix::Library;
+#[cfg(windows)]
+use libloading::os::windows::Library;
+
+/// The core functions, set in `PluginStartup`.
+static CORE_API: RwLock<Option<CoreApi>> = RwLock::new(None);
+
+type CoreGetApiVersions =
+    unsafe extern "C" fn(*mut c_int, *mut c_int, *mut c_int, *mut c_int) -> m64p_error;
+type ConfigGetUserConfigPath = unsafe extern "C" fn() -> *const c_char;
+type CoreDoCommand = unsafe extern "C" fn(c_int, c_int, *mut c_void) -> m64p_error;
+
+/// The core functions, looked up once when the plugin starts.
+#[derive(Debug, Copy, Clone)]
+pub struct CoreApi {
+    core_get_api_versions: CoreGetApiVersions,
+    config_get_user_config_path: Option<ConfigGetUserConfigPath>,
+    core_do_command: Option<CoreDoCommand>,
+}
+
+impl CoreApi {
+    /// Look up the core functions. Returns `None` if a required function is missing. Functions that are only used
+    /// for optional features are `None` when missing, and their methods return an error.
+    ///
+    /// # Safety
+    ///
+    /// `core_lib_handle` must be a handle to the mupen64plus-core dynamic library, which stays loaded while the
+    /// plugin is used.
+    pub unsafe fn load(core_lib_handle: m64p_dynlib_handle) -> Option<Self> {
+        // Make sure to NOT free the library associated with the handle
+        let lib = ManuallyDrop::new(Library::from_raw(core_lib_handle.cast()));
+
+        Some(CoreApi {
+            core_get_api_versions: *lib.get(b"CoreGetAPIVersions\0").ok()?,
+            config_get_user_config_path: lib.get(b"ConfigGetUserConfigPath\0").ok().map(|sym| *sym),
+            core_do_command: lib.get(b"CoreDoCommand\0").ok().map(|sym| *sym),
+        })
+    }
+
+    /// Get the core functions, if the plugin was started.
+    pub fn get() -> Option<CoreApi> {
+        *CORE_API.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Set the core functions that are returned by `CoreApi::get`.
+    pub fn set(api: Option<CoreApi>) {
+        *CORE_API.write().unwrap_or_else(PoisonError::into_inner) = api;
+    }
+
+    /// Get the version of the core API.
+    pub fn core_api_version(&self) -> c_int {
+        let mut version = 0;
+        unsafe {
+            (self.core_get_api_versions)(
+                &mut version,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+        }
+
+        version
+    }
+
+    /// Get the directory of the user configuration files.
+    pub fn config_get_user_config_path(&self) -> Option<String> {
+        let path = unsafe { (self.config_get_user_config_path?)() };
+        if path.is_null() {
+            return None;
+        }
+
+        Some(
+            unsafe { CStr::from_ptr(path) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+
+    /// Reset the running ROM, like the reset button of the console (soft reset) or turning it off and on (hard reset).
+    pub fn reset(&self, hard: bool) -> Result<(), m64p_error> {
+        let do_command = self.core_do_command.ok_or(m64p_error_M64ERR_UNSUPPORTED)?;
+
+        let e = unsafe { do_command(M64CMD_RESET, hard as c_int, ptr::null_mut()) };
+        if e == m64p_error_M64ERR_SUCCESS {
+            Ok(())
+        } else {
+            Err(e)
+        }
+    }
+}
@@ -1,8 +1,9 @@
+use serde::{Deserialize, Serialize};
 use std::{
     ffi::{c_void, CString},
     os::raw::{c_char, c_int},
     sync::{
-        atomic::{AtomicPtr, Ordering},
+        atomic::{AtomicPtr, AtomicU8, Ordering},
         Mutex,
     },
 };
@@ -10,17 +11,34 @@ use std::{
 pub type DebugCallback = extern "C" fn(*mut c_void, c_int, *const c_char);
 
 pub fn init(debug_callback: DebugCallback, context_ptr: *mut c_void) {
-    *DEBUG_INFO.lock().unwrap() = Some(DebugInfo::new(debug_callback, context_ptr));
+    *crate::lock(&DEBUG_INFO) = Some(DebugInfo::new(debug_callback, context_ptr));
 }
 
 pub static DEBUG_INFO: Mutex<Option<DebugInfo>> = Mutex::new(None);
 
+/// The most verbose level that is printed. Everything is printed in debug builds by default.
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(if cfg!(debug_assertions) {
+    M64Message::Verbose as u8
+} else {
+    M64Message::Warning as u8
+});
+
+pub fn set_log_level(level: M64Message) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Check if messages of the given level are printed. Status messages are meant for the user, and are always printed.
+pub fn is_enabled(level: M64Message) -> bool {
+    level == M64Message::Status || level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Print a message through the core, if the level is enabled. The message is only formatted if it is printed.
 macro_rules! debug_print {
     ($level:expr, $s:expr) => {
         debug_print!($level, $s,)
     };
     ($level:expr, $s:expr, $($arg:expr),*) => {{
-        if cfg!(debug_assertions) || $level <= $crate::debug::M64Message::Warning {
+        if $crate::debug::is_enabled($level) {
             $crate::debug::__print_debug_message($level, format!($s $(, $arg)*));
         }
     }};
@@ -28,9 +46,10 @@ macro_rules! debug_print {
 
 #[doc(hidden)]
 pub(crate) fn __print_debug_message(level: M64Message, message: String) {
-    match *DEBUG_INFO.lock().unwrap() {
+    match *crate::lock(&DEBUG_INFO) {
         Some(ref di) => {
-            let message = CString::new(message).unwrap();
+            // Null bytes can not be passed to the core, and are dropped instead
+            let message = CString::new(message.replace('\0', "")).unwrap_or_default();
             let context = di.context_ptr.load(Ordering::Acquire);
 
             (di.callback)(context, level as c_int, message.as_ptr());
@@ -41,8 +60,7 @@ pub(crate) fn __print_debug_message(level: M64Message, message: String) {
     }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, PartialOrd, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Eq, Deserialize, Serialize)]
 pub enum M64Message {
     Error = 1,
     Warning,
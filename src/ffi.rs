@@ -4,6 +4,8 @@
 #[allow(non_snake_case)]
 #[allow(dead_code)]
 #[allow(clippy::all)]
+#[allow(unnecessary_transmutes)]
+#[allow(deref_nullptr)]
 pub mod unix;
 #[cfg(unix)]
 pub use unix::*;
@@ -14,10 +16,20 @@ pub use unix::*;
 #[allow(non_snake_case)]
 #[allow(dead_code)]
 #[allow(clippy::all)]
+#[allow(unnecessary_transmutes)]
+#[allow(deref_nullptr)]
 pub mod windows;
 #[cfg(windows)]
 pub use windows::*;
 
+// Controller plugins (paks) from `m64p_plugin.h`, which are not generated by bindgen
+pub const PLUGIN_NONE: std::os::raw::c_int = 1;
+pub const PLUGIN_MEMPAK: std::os::raw::c_int = 2;
+pub const PLUGIN_RUMBLE_PAK: std::os::raw::c_int = 3;
+
+// Core commands (`m64p_command`) from `m64p_types.h`, which are not generated by bindgen
+pub const M64CMD_RESET: std::os::raw::c_int = 19;
+
 #[cfg(feature = "m64p_compat")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
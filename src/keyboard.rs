@@ -0,0 +1,115 @@
+//! Keyboard input, used for the keyboard port while no controller is connected to it.
+
+use crate::{buttons::N64Buttons, config::KeyboardMapping, stick::N64_STICK_MAX};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Mask of the N64 buttons, the stick directions are stored in the bits above them.
+const BUTTONS: u32 = N64Buttons::BUTTONS_MASK;
+const STICK_LEFT: u32 = 1 << 16;
+const STICK_RIGHT: u32 = 1 << 17;
+const STICK_DOWN: u32 = 1 << 18;
+const STICK_UP: u32 = 1 << 19;
+
+/// The N64 buttons and stick directions that are held on the keyboard.
+#[derive(Debug)]
+pub struct Keyboard {
+    pressed: AtomicU32,
+}
+
+impl Keyboard {
+    pub const fn new() -> Self {
+        Keyboard {
+            pressed: AtomicU32::new(0),
+        }
+    }
+
+    pub fn key_down(&self, mapping: &KeyboardMapping, keysym: i32) {
+        self.pressed
+            .fetch_or(key_mask(mapping, keysym), Ordering::AcqRel);
+    }
+
+    pub fn key_up(&self, mapping: &KeyboardMapping, keysym: i32) {
+        self.pressed
+            .fetch_and(!key_mask(mapping, keysym), Ordering::AcqRel);
+    }
+
+    /// Release all keys, so that no key is stuck when its key up event is missed.
+    pub fn release_all(&self) {
+        self.pressed.store(0, Ordering::Release);
+    }
+
+    /// Get the bit pattern of the held N64 buttons.
+    pub fn buttons(&self) -> u32 {
+        self.pressed.load(Ordering::Acquire) & BUTTONS
+    }
+
+    /// Get the stick position, where each held direction moves the stick fully in that direction.
+    pub fn stick(&self) -> (i8, i8) {
+        let pressed = self.pressed.load(Ordering::Acquire);
+        let axis = |negative, positive| {
+            let held = |direction| pressed & direction != 0;
+            match (held(negative), held(positive)) {
+                (true, false) => -N64_STICK_MAX,
+                (false, true) => N64_STICK_MAX,
+                _ => 0,
+            }
+        };
+
+        (axis(STICK_LEFT, STICK_RIGHT), axis(STICK_DOWN, STICK_UP))
+    }
+}
+
+/// Get the bits of everything that is mapped to the key. Modifier keys are not taken into account, so a mapped key
+/// also works while e.g. shift is held.
+fn key_mask(mapping: &KeyboardMapping, keysym: i32) -> u32 {
+    let stick = [
+        (mapping.stick_left, STICK_LEFT),
+        (mapping.stick_right, STICK_RIGHT),
+        (mapping.stick_down, STICK_DOWN),
+        (mapping.stick_up, STICK_UP),
+    ];
+
+    mapping
+        .buttons()
+        .into_iter()
+        .map(|(key, button)| (key, button.bit_pattern()))
+        .chain(stick)
+        .filter(|&(key, _)| key == Some(keysym))
+        .fold(0, |mask, (_, bits)| mask | bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_held_keys() {
+        let mapping = KeyboardMapping {
+            a: Some(120),
+            start: Some(13),
+            stick_left: Some(97),
+            stick_right: Some(100),
+            ..Default::default()
+        };
+        let keyboard = Keyboard::new();
+
+        keyboard.key_down(&mapping, 120);
+        keyboard.key_down(&mapping, 13);
+        keyboard.key_down(&mapping, 97);
+        keyboard.key_down(&mapping, 1);
+        assert_eq!(keyboard.buttons(), 0x0090);
+        assert_eq!(keyboard.stick(), (-N64_STICK_MAX, 0));
+
+        keyboard.key_down(&mapping, 100);
+        assert_eq!(keyboard.stick(), (0, 0));
+
+        keyboard.key_up(&mapping, 120);
+        keyboard.key_up(&mapping, 97);
+        assert_eq!(keyboard.buttons(), 0x0010);
+        assert_eq!(keyboard.stick(), (N64_STICK_MAX, 0));
+
+        keyboard.release_all();
+        assert_eq!(keyboard.buttons(), 0);
+        assert_eq!(keyboard.stick(), (0, 0));
+    }
+}
@@ -1,37 +1,66 @@
 #[macro_use]
 mod debug;
 pub mod adapter;
+mod buttons;
+mod calibration;
 pub mod config;
+mod core_api;
 mod ffi;
+mod keyboard;
+mod macros;
+mod mapping;
+mod pif;
+mod recording;
+mod repeat;
+mod reset;
+mod shift;
+mod socd;
+mod stick;
+mod turbo;
+mod watchdog;
 #[macro_use]
 mod static_cstr;
 
-use adapter::AdapterState;
-use config::Config;
+use adapter::{AdapterState, AtomicControllerState, Channel, ControllerState};
+use buttons::N64Buttons;
+use calibration::CalibrationFile;
+use config::{
+    Config, ConfigWatcher, KeyboardMapping, MergeMode, N64Button, Pak, PollMode, SocdMode,
+};
+use core_api::CoreApi;
 use debug::M64Message;
 use ffi::*;
-use once_cell::sync::OnceCell;
+use keyboard::Keyboard;
+use macros::Macros;
+use mapping::{CStick, Triggers};
+use recording::{Playback, Recorder, Recording};
+use repeat::DPadRepeat;
+use reset::ResetChord;
+use shift::Shift;
+use socd::Socd;
 use static_cstr::StaticCStr;
 use std::{
-    ffi::{c_void, CStr},
-    mem::ManuallyDrop,
+    convert::TryFrom,
+    ffi::c_void,
+    fs, io, iter,
     os::raw::{c_char, c_int, c_uchar},
-    path::Path,
-    ptr,
+    path::{Path, PathBuf},
+    ptr, slice,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Mutex,
+        atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Condvar, Mutex, MutexGuard, PoisonError,
     },
-    thread,
-    time::Duration,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
+use stick::StickFilter;
+use turbo::Turbo;
+use watchdog::Watchdog;
 
-#[cfg(unix)]
-use libloading::os::unix::Library;
-#[cfg(windows)]
-use libloading::os::windows::Library;
-
-use crate::adapter::GcAdapter;
+use crate::adapter::{
+    AdapterSource, ConnectionStatus, GcAdapter, MockAdapter, ReconnectBackoff, RumbleWriter,
+};
 
 struct PluginInfo {
     name: StaticCStr,
@@ -41,15 +70,310 @@ struct PluginInfo {
 
 static PLUGIN_INFO: PluginInfo = PluginInfo {
     name: static_cstr!("GC Adapter (for Wii U or Switch) Input Plugin"),
-    version: 0x000600,            // v0.6.0
+    version: parse_version(env!("CARGO_PKG_VERSION")),
     target_api_version: 0x020100, // v2.1.0
 };
 
+const _: () = assert!(parse_version("0.6.0") == 0x000600);
+const _: () = assert!(parse_version("1.12.255-beta.1") == 0x010CFF);
+
+/// Convert a `major.minor.patch` version to the `0xMMmmpp` form of the core, ignoring any pre-release or build
+/// metadata. Fails to compile when used in a constant with an invalid version.
+const fn parse_version(version: &str) -> c_int {
+    let bytes = version.as_bytes();
+    let mut parts = [0; 3];
+    let mut part = 0;
+    let mut i = 0;
+
+    while i < bytes.len() && bytes[i] != b'-' && bytes[i] != b'+' {
+        match bytes[i] {
+            b'.' if part < 2 => part += 1,
+            b @ b'0'..=b'9' => {
+                parts[part] = parts[part] * 10 + (b - b'0') as c_int;
+                assert!(parts[part] <= 0xFF, "version part is too large");
+            }
+            _ => panic!("invalid version"),
+        }
+        i += 1;
+    }
+    assert!(part == 2, "version must have three parts");
+
+    parts[0] << 16 | parts[1] << 8 | parts[2]
+}
+
 static IS_INIT: AtomicBool = AtomicBool::new(false);
 
-static CONFIG: OnceCell<Config> = OnceCell::new();
+/// The active configuration. Configurations are leaked when they are replaced, so that references to them stay valid
+/// without locking. This only happens when a reload finds a changed configuration, or when the plugin is started
+/// again.
+static CONFIG: AtomicPtr<Config> = AtomicPtr::new(ptr::null_mut());
+
+/// Watches the configuration file. Locked while the configuration is reloaded, so that only one reload is done at a
+/// time.
+static CONFIG_WATCHER: Mutex<Option<ConfigWatcher>> = Mutex::new(None);
+
+/// The state of the controller mapped to each N64 channel, written by the adapter thread.
+static CONTROLLER_STATES: [AtomicControllerState; 4] = [
+    AtomicControllerState::new(),
+    AtomicControllerState::new(),
+    AtomicControllerState::new(),
+    AtomicControllerState::new(),
+];
+
+/// State that is kept between `GetKeys` calls for a channel.
+struct ChannelState {
+    turbo: Turbo,
+    d_pad_repeat: DPadRepeat,
+    socd: Socd,
+    macros: Macros,
+    shift: Shift,
+    reset: ResetChord,
+    /// How many reads had each bit of the `BUTTONS` value set, with `log_button_counts`.
+    button_counts: [u32; 16],
+    c_stick: CStick,
+    triggers: Triggers,
+}
+
+impl ChannelState {
+    const fn new() -> Self {
+        ChannelState {
+            turbo: Turbo::new(),
+            d_pad_repeat: DPadRepeat::new(),
+            socd: Socd::new(),
+            macros: Macros::new(),
+            shift: Shift::new(),
+            reset: ResetChord::new(),
+            button_counts: [0; 16],
+            c_stick: CStick::new(),
+            triggers: Triggers::new(),
+        }
+    }
+}
+
+/// The connected adapters and the state that was last read from them. Only the adapter thread uses them, except with
+/// `PollMode::Direct`, where `GetKeys` reads from them.
+struct Adapters {
+    sources: Vec<Box<dyn AdapterSource>>,
+    states: Vec<AdapterState>,
+    /// When each adapter has been connected for `adapter_startup_delay`, before which its reads are discarded.
+    ready_at: Vec<Instant>,
+    last_read: Option<Instant>,
+    /// Set when adapters are added or removed.
+    changed: bool,
+    /// Set when adapters are removed because they could not be read from.
+    lost: bool,
+    /// The calibrations that finished since they were last saved: the adapter id, port and stick offsets.
+    calibrated: Vec<(String, usize, [i8; 4])>,
+    /// The control stick and C-stick filter of each channel.
+    filters: [[StickFilter; 2]; 4],
+}
+
+impl Adapters {
+    const fn new() -> Self {
+        Adapters {
+            sources: Vec::new(),
+            states: Vec::new(),
+            ready_at: Vec::new(),
+            last_read: None,
+            changed: false,
+            lost: false,
+            calibrated: Vec::new(),
+            filters: [[StickFilter::new(); 2]; 4],
+        }
+    }
+
+    /// Add newly connected adapters, which use the calibrations saved in `calibrations`.
+    fn add(
+        &mut self,
+        sources: Vec<Box<dyn AdapterSource>>,
+        cfg: &Config,
+        calibrations: &CalibrationFile,
+    ) {
+        let len = self.sources.len() + sources.len();
+        self.states.extend(
+            sources
+                .iter()
+                .map(|source| AdapterState::with_saved(calibrations.offsets(source.id()))),
+        );
+        self.ready_at
+            .resize(len, Instant::now() + cfg.adapter_startup_delay());
+        self.sources.extend(sources);
+        self.changed = true;
+    }
+
+    /// Read from all adapters, waiting at most `timeout` for each, and store the controller states. Adapters that
+    /// could not be read from are removed.
+    fn read(&mut self, cfg: &Config, timeout: Duration) {
+        let mut i = 0;
+        while i < self.sources.len() {
+            match self.sources[i].read_blocking(timeout) {
+                Ok(buf) => {
+                    // Keep the previous state if the adapter did not send anything new, or is still starting
+                    if let Some(buf) = buf.filter(|_| Instant::now() >= self.ready_at[i]) {
+                        let calibrated =
+                            self.states[i].update(buf, cfg.use_origin, cfg.calibrate_on_connect);
+                        for port in (0..4).filter(|&port| calibrated[port]) {
+                            let offsets = self.states[i].calibration[port].offsets;
+                            self.calibrated
+                                .push((self.sources[i].id().to_owned(), port, offsets));
+                        }
+                    }
+                    i += 1;
+                }
+                Err(e) => {
+                    if e == rusb::Error::NoDevice {
+                        debug_print!(M64Message::Info, "Adapter disconnected");
+                    } else {
+                        debug_print!(
+                            M64Message::Warning,
+                            "Error while reading from adapter ({:?}), reconnecting",
+                            e
+                        );
+                    }
+
+                    self.sources.remove(i);
+                    self.states.remove(i);
+                    self.ready_at.remove(i);
+                    self.changed = true;
+                    self.lost = true;
+                }
+            }
+        }
+
+        self.last_read = Some(Instant::now());
+        self.store_states(cfg);
+        ADAPTERS_READ.notify_all();
+    }
+
+    /// Store the state of the controller mapped to each channel, with the sticks smoothed.
+    fn store_states(&mut self, cfg: &Config) {
+        let now = Instant::now();
+
+        // Disconnected adapters are removed, so their inputs are not reported anymore
+        let (pinned, sources) = (cfg.pinned_adapters(), cfg.source_ports());
+        let ids: Vec<_> = self.sources.iter().map(|source| source.id()).collect();
+        let mapping = adapter::map_channels(&self.states, &ids, pinned, sources);
+        for (i, ((controller, source), [stick, c_stick])) in CONTROLLER_STATES
+            .iter()
+            .zip(mapping)
+            .zip(&mut self.filters)
+            .enumerate()
+        {
+            let mut s = if cfg.merge_mode == MergeMode::Separate || pinned[i].is_some() {
+                source
+                    .map(|(adapter, port)| self.states[adapter].controller_state(port))
+                    .unwrap_or_default()
+            } else {
+                let states = self.states.iter().map(|s| s.controller_state(sources[i]));
+                ControllerState::merge(states, cfg.merge_mode)
+            };
+
+            if s.connected {
+                (s.stick_x, s.stick_y) =
+                    stick.apply((s.stick_x, s.stick_y), cfg.control_stick_smoothing, now);
+                (s.substick_x, s.substick_y) =
+                    c_stick.apply((s.substick_x, s.substick_y), cfg.c_stick_smoothing, now);
+            } else {
+                stick.reset();
+                c_stick.reset();
+            }
+
+            controller.store(s);
+        }
+        ADAPTER_COUNT.store(self.states.len(), Ordering::Release);
+    }
+
+    /// Get the channels that each port of each adapter rumbles for, see `adapter::rumble_routes`. Controllers that are
+    /// combined into one channel all rumble for it.
+    fn rumble_routes(&self, cfg: &Config) -> Vec<[u8; 4]> {
+        let channels = [Channel::One, Channel::Two, Channel::Three, Channel::Four];
+        let (pinned, sources) = (cfg.pinned_adapters(), cfg.source_ports());
+        let ids: Vec<_> = self.sources.iter().map(|source| source.id()).collect();
+        let mapping = adapter::map_channels(&self.states, &ids, pinned, sources);
+
+        let mut routed = Vec::new();
+        for (i, &channel) in channels.iter().enumerate() {
+            if cfg.merge_mode == MergeMode::Separate || pinned[i].is_some() {
+                routed.extend(mapping[i].map(|source| (channel, source)));
+            } else {
+                routed
+                    .extend((0..self.states.len()).map(|adapter| (channel, (adapter, sources[i]))));
+            }
+        }
+
+        adapter::rumble_routes(self.sources.len(), &routed)
+    }
+
+    fn clear(&mut self) {
+        *self = Adapters::new();
+        for controller in &CONTROLLER_STATES {
+            controller.store(Default::default());
+        }
+        ADAPTER_COUNT.store(0, Ordering::Release);
+    }
+}
+
+static ADAPTERS: Mutex<Adapters> = Mutex::new(Adapters::new());
+
+/// Notified after the adapters are read, for waiting on the adapter thread with `ADAPTERS` locked.
+static ADAPTERS_READ: Condvar = Condvar::new();
+
+/// The number of connected adapters, updated after the controller states.
+static ADAPTER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+static KEYBOARD: Keyboard = Keyboard::new();
+
+static CHANNEL_STATES: Mutex<[ChannelState; 4]> = Mutex::new([
+    ChannelState::new(),
+    ChannelState::new(),
+    ChannelState::new(),
+    ChannelState::new(),
+]);
+
+/// The ports that were reported to the core as present in `InitiateControllers`, one bit per port.
+static PRESENT_PORTS: AtomicU8 = AtomicU8::new(0b1111);
+
+/// The profile (starting from 1, 0 for none) that each port uses, see `Config::profile`.
+static ACTIVE_PROFILES: [AtomicUsize; 4] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Whether the input is recorded or played back, set when a ROM is opened.
+static RECORDING: Mutex<Recording> = Mutex::new(Recording::Off);
+
+/// Set to make the adapter thread calibrate the sticks of all connected controllers.
+static CALIBRATE: AtomicBool = AtomicBool::new(false);
+
+/// Cleared while the emulator window does not have focus, see `GCASetFocus`.
+static FOCUSED: AtomicBool = AtomicBool::new(true);
+
+/// Fed by the adapter thread on every iteration, and checked in `GetKeys` with `adapter_thread_watchdog`.
+static ADAPTER_THREAD_WATCHDOG: Watchdog = Watchdog::new();
+
+/// Set by `lock` when it recovered a mutex that was poisoned by a panic, so that a warning is logged once.
+static RECOVERED_POISON: AtomicBool = AtomicBool::new(false);
+
+static THREADS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+static RUMBLE_SENDER: Mutex<Option<Sender<RumbleCommand>>> = Mutex::new(None);
 
-static ADAPTER_STATE: Mutex<AdapterState> = Mutex::new(AdapterState::new());
+/// The read loop that `GetKeys` runs with the `sync-read` feature, instead of the adapter thread.
+static SYNC_READ_LOOP: Mutex<Option<ReadLoop>> = Mutex::new(None);
+
+/// The rumble that `ControllerCommand` writes with the `sync-read` feature, instead of the rumble thread.
+static SYNC_RUMBLE: Mutex<Rumble> = Mutex::new(Rumble::new());
+
+/// A command for the rumble thread.
+enum RumbleCommand {
+    /// Adapters were connected or disconnected, or controllers were moved between channels. Rumble is written to each
+    /// adapter for the channels that each of its ports rumbles for, one bit per channel.
+    Adapters(Vec<(RumbleWriter, [u8; 4])>),
+    /// Turn rumble on or off for a channel.
+    Set(Channel, bool),
+}
 
 /// Start up the plugin.
 ///
@@ -81,14 +405,8 @@ pub unsafe extern "C" fn PluginStartup(
     debug::init(debug_callback, context);
     debug_print!(M64Message::Info, "PluginStartup called");
 
-    // Make sure to NOT free the library associated with the handle
-    let lib = ManuallyDrop::new(Library::from_raw(core_lib_handle.cast()));
-
-    let core_api_version_fn = if let Ok(sym) =
-        lib.get::<extern "C" fn(*mut c_int, *mut c_int, *mut c_int, *mut c_int)>(
-            b"CoreGetAPIVersions\0",
-        ) {
-        sym
+    let core_api = if let Some(api) = CoreApi::load(core_lib_handle) {
+        api
     } else {
         debug_print!(
             M64Message::Error,
@@ -96,14 +414,9 @@ pub unsafe extern "C" fn PluginStartup(
         );
         return m64p_error_M64ERR_INPUT_INVALID;
     };
+    CoreApi::set(Some(core_api));
 
-    let mut core_ver = 0;
-    core_api_version_fn(
-        &mut core_ver as *mut _,
-        ptr::null_mut(),
-        ptr::null_mut(),
-        ptr::null_mut(),
-    );
+    let core_ver = core_api.core_api_version();
 
     debug_print!(
         M64Message::Info,
@@ -121,23 +434,20 @@ pub unsafe extern "C" fn PluginStartup(
         return m64p_error_M64ERR_INCOMPATIBLE;
     }
 
-    start_read_thread();
-
     let cfg_file_name = "mupen64plus-input-gca.toml";
-    let cfg_path = if let Ok(sym) =
-        lib.get::<extern "C" fn() -> *const c_char>(b"ConfigGetUserConfigPath\0")
-    {
-        let usr_cfg_dir = CStr::from_ptr(sym()).to_string_lossy();
-        Path::new(usr_cfg_dir.as_ref()).join(cfg_file_name)
+    let cfg_path = if let Some(path) = std::env::var_os("MUPEN64PLUS_INPUT_GCA_CONFIG") {
+        // For portable setups, or switching between configurations
+        PathBuf::from(path)
+    } else if let Some(usr_cfg_dir) = core_api.config_get_user_config_path() {
+        Path::new(&usr_cfg_dir).join(cfg_file_name)
     } else {
         debug_print!(
             M64Message::Error,
             "Could not find function for getting user config path"
         );
 
-        Path::new(cfg_file_name)
-            .canonicalize()
-            .expect("could not canonicalize relative path")
+        let path = Path::new(cfg_file_name);
+        path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
     };
 
     debug_print!(
@@ -146,11 +456,35 @@ pub unsafe extern "C" fn PluginStartup(
         cfg_path.display()
     );
 
-    // Ignore error if the cell was alredy initialized
-    let _ = CONFIG.set(Config::read_from_file(&cfg_path).unwrap_or_else(|e| {
+    let cfg = Config::read_from_file(&cfg_path).unwrap_or_else(|e| {
         debug_print!(M64Message::Error, "Config error: {:?}", e);
         Config::create(&cfg_path).unwrap_or_else(|e| e)
-    }));
+    });
+    let cfg = set_config(cfg, true);
+
+    let calibrations = CalibrationFile::load(cfg_path.with_file_name(calibration::FILE_NAME));
+    *lock(&CONFIG_WATCHER) = Some(ConfigWatcher::new(cfg_path));
+    let read_loop = ReadLoop::new(cfg, calibrations, mock_adapter());
+    if cfg!(feature = "sync-read") {
+        debug_print!(M64Message::Info, "Reading the adapter without threads");
+        *lock(&SYNC_READ_LOOP) = Some(read_loop);
+        return m64p_error_M64ERR_SUCCESS;
+    }
+
+    let (rumble_tx, rumble_rx) = mpsc::channel();
+    *lock(&RUMBLE_SENDER) = Some(rumble_tx);
+
+    let threads =
+        start_read_thread(read_loop).and_then(|read| Ok([read, start_rumble_thread(rumble_rx)?]));
+    match threads {
+        Ok(threads) => lock(&THREADS).extend(threads),
+        Err(e) => {
+            debug_print!(M64Message::Error, "Could not start plugin threads: {}", e);
+            IS_INIT.store(false, Ordering::Release);
+            lock(&RUMBLE_SENDER).take();
+            return m64p_error_M64ERR_SYSTEM_FAIL;
+        }
+    }
 
     m64p_error_M64ERR_SUCCESS
 }
@@ -158,17 +492,58 @@ pub unsafe extern "C" fn PluginStartup(
 /// Shut down the plugin.
 ///
 /// This function is not unsafe, but if this is not called then the adapter thread will continue running.
+///
+/// Waits (up to one second) for the plugin threads to stop, so that they are not using the adapter while the plugin
+/// is unloaded.
 #[no_mangle]
 pub extern "C" fn PluginShutdown() -> m64p_error {
+    const JOIN_TIMEOUT: Duration = Duration::from_secs(1);
+
     debug_print!(M64Message::Info, "PluginShutdown called");
 
     IS_INIT.store(false, Ordering::Release);
+    // The rumble thread handles the pending commands before it stops, which is when all senders are dropped
+    stop_rumble();
+    lock(&RUMBLE_SENDER).take();
+    lock(&SYNC_READ_LOOP).take();
+    lock(&CONFIG_WATCHER).take();
+    *lock(&SYNC_RUMBLE) = Rumble::new();
+    CoreApi::set(None);
+
+    let deadline = Instant::now() + JOIN_TIMEOUT;
+    for handle in lock(&THREADS).drain(..) {
+        // Wake the thread if it is waiting between reads or connection attempts
+        handle.thread().unpark();
+
+        while !handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        if handle.is_finished() {
+            if handle.join().is_err() {
+                debug_print!(M64Message::Error, "Thread stopped after a panic");
+            }
+        } else {
+            debug_print!(
+                M64Message::Warning,
+                "Thread {:?} did not stop in time",
+                handle.thread().name()
+            );
+        }
+    }
+
+    // The adapter thread closes the adapters when it stops, but not if it did not stop in time. Closing them releases
+    // the USB interface, so that the adapter can be opened again when the plugin is started again.
+    lock(&ADAPTERS).clear();
 
     m64p_error_M64ERR_SUCCESS
 }
 
 /// Get the plugin type, version, target API version, name, and capabilities.
 ///
+/// The plugin API does not define any capabilities for input plugins, so none are reported. Rumble support is
+/// reported to the core per controller instead, by inserting a rumble pak in `InitiateControllers`.
+///
 /// # Safety
 ///
 /// The caller has to make sure the given pointers are valid.
@@ -215,93 +590,279 @@ pub unsafe extern "C" fn InitiateControllers(control_info: CONTROL_INFO) {
     #[cfg(feature = "m64p_compat")]
     let controls = controls as *mut CONTROL_M64P;
 
-    for i in 0..4 {
+    let present = match config() {
+        Some(cfg) if !cfg.all_controllers_present => present_ports(cfg),
+        Some(cfg) => std::array::from_fn(|i| cfg.port(i).enabled),
+        None => [true; 4],
+    };
+    let mask = present
+        .iter()
+        .enumerate()
+        .fold(0, |mask, (i, &p)| mask | (p as u8) << i);
+    PRESENT_PORTS.store(mask, Ordering::Release);
+
+    for (i, present) in present.into_iter().enumerate() {
+        // The paks are emulated by the core, which also stores the controller pak contents. The core also answers the
+        // status command with the pak set here, so it reports a rumble or controller pak as present
+        let pak = config().map_or(Pak::None, |cfg| cfg.pak(i));
+        debug_print!(M64Message::Info, "Port {}: {:?} pak", i + 1, pak);
+        let plugin = match pak {
+            Pak::None => PLUGIN_NONE,
+            Pak::Memory => PLUGIN_MEMPAK,
+            Pak::Rumble => PLUGIN_RUMBLE_PAK,
+        };
+
         (*controls.add(i)).RawData = 0;
-        (*controls.add(i)).Present = 1;
+        (*controls.add(i)).Present = present as c_int;
+        (*controls.add(i)).Plugin = plugin;
+    }
+}
+
+/// Get the ports that have a controller connected, are controlled by the keyboard, or are forced with `force_present`.
+/// Waits (up to one second) for the adapter thread to find an adapter first, and then for the input of a newly found
+/// adapter to no longer be ignored (see `adapter_startup_delay`). With the `sync-read` feature, the adapters are looked
+/// for and read here instead.
+///
+/// All enabled ports are reported as present if no adapter or controller is found, so that controllers can still be
+/// connected later.
+fn present_ports(cfg: &Config) -> [bool; 4] {
+    const ADAPTER_TIMEOUT: Duration = Duration::from_secs(1);
+
+    let adapters = if cfg!(feature = "sync-read") {
+        // Without the adapter thread, nothing else looks for the adapters
+        let deadline = Instant::now() + ADAPTER_TIMEOUT;
+        loop {
+            let wait = step_sync_read_loop().unwrap_or_default();
+            let now = Instant::now();
+            if !lock(&ADAPTERS).sources.is_empty() || now >= deadline {
+                break lock(&ADAPTERS);
+            }
+            thread::sleep(wait.min(deadline - now));
+        }
+    } else {
+        wait_for_read(lock(&ADAPTERS), ADAPTER_TIMEOUT, |a| a.sources.is_empty())
+    };
+
+    // The controllers only show up once the input of the adapter is no longer ignored, which only takes a while if
+    // the adapter was just found
+    let now = Instant::now();
+    let ready_at = adapters.ready_at.iter().max().copied().filter(|&t| t > now);
+    if poll_mode(cfg) == PollMode::Direct {
+        // Nothing else reads the adapters until the game asks for input
+        drop(adapters);
+        if let Some(ready_at) = ready_at {
+            thread::sleep(ready_at - now);
+        }
+        read_direct(cfg);
+    } else if let Some(ready_at) = ready_at {
+        drop(wait_for_read(
+            adapters,
+            ready_at - now + ADAPTER_TIMEOUT,
+            |a| !a.last_read.is_some_and(|read| read >= ready_at),
+        ));
+    } else {
+        drop(adapters);
     }
 
-    if !ADAPTER_STATE.lock().unwrap().any_connected() {
+    let enabled: [bool; 4] = std::array::from_fn(|i| cfg.port(i).enabled);
+    let present: [bool; 4] = std::array::from_fn(|i| {
+        enabled[i]
+            && (cfg.port(i).force_present
+                || CONTROLLER_STATES[i].load().connected
+                || (i + 1 == cfg.keyboard_port && keyboard_mapping(cfg).any_mapped()))
+    });
+
+    if !present.contains(&true) {
         debug_print!(
             M64Message::Warning,
             "No controllers connected, but hotplugging is supported"
         );
+        return enabled;
     }
+
+    present
 }
 
-/// Get the state of the buttons by reading from the adapter.
+/// Get the state of the buttons by reading from the adapter, or from the recording that is played back.
 ///
 /// # Safety
 ///
-/// `keys` must point to an intialized `BUTTONS` union.
+/// `keys` must be null or point to an intialized `BUTTONS` union.
 #[no_mangle]
 pub unsafe extern "C" fn GetKeys(control: c_int, keys: *mut BUTTONS) {
-    let s = ADAPTER_STATE.lock().unwrap().controller_state(control);
-    if !s.connected {
+    // Unlike the other functions, this and ControllerCommand are not logged when called, since they are called for
+    // every port on every frame
+    // The core may pass -1, or any other value outside of the four controllers
+    let channel = match Channel::try_from(control) {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+    if keys.is_null() {
         return;
     }
+    warn_recovered_poison();
+    step_sync_read_loop();
+
+    let cfg = match config() {
+        Some(cfg) => cfg,
+        None => return,
+    };
+    // Without the adapter thread, there is nothing to watch
+    if cfg.adapter_thread_watchdog && !cfg!(feature = "sync-read") {
+        check_adapter_thread();
+    }
 
     let keys = &mut *keys;
-    keys.Value = 0;
+    let mut recording = lock(&RECORDING);
+    if let Recording::Playback(playback) = &mut *recording {
+        keys.Value = playback.next(channel as usize);
+        return;
+    }
 
-    let cfg = CONFIG.get().unwrap();
-    let (stick_x, stick_y) =
-        s.stick_with_deadzone(cfg.control_stick_deadzone, cfg.control_stick_sensitivity);
-    let (substick_x, substick_y) = s.substick_with_deadzone(cfg.c_stick_deadzone);
+    read_keys(keys, cfg, channel);
 
-    if s.right {
-        keys.Value |= cfg.controller_mapping.d_pad_right.bit_pattern();
-    }
-    if s.left {
-        keys.Value |= cfg.controller_mapping.d_pad_left.bit_pattern();
-    }
-    if s.down {
-        keys.Value |= cfg.controller_mapping.d_pad_down.bit_pattern();
-    }
-    if s.up {
-        keys.Value |= cfg.controller_mapping.d_pad_up.bit_pattern();
-    }
-    if s.start {
-        keys.Value |= cfg.controller_mapping.start.bit_pattern();
-    }
-    if s.a {
-        keys.Value |= cfg.controller_mapping.a.bit_pattern();
-    }
-    if s.b {
-        keys.Value |= cfg.controller_mapping.b.bit_pattern();
+    if let Recording::Record(recorder) = &mut *recording {
+        if let Err(e) = recorder.record(channel as usize, keys.Value) {
+            debug_print!(M64Message::Error, "Could not record input: {}", e);
+            *recording = Recording::Off;
+        }
     }
-    if s.x {
-        keys.Value |= cfg.controller_mapping.x.bit_pattern();
+}
+
+/// Get the state of the buttons of the controller mapped to `channel`, or of the keyboard if there is none.
+fn read_keys(keys: &mut BUTTONS, cfg: &Config, channel: Channel) {
+    // The core keeps the previous value otherwise, which could hold buttons
+    if !cfg.port(channel).enabled || (cfg.neutral_on_focus_loss && !FOCUSED.load(Ordering::Acquire))
+    {
+        *keys = N64Buttons::new().into();
+        return;
     }
-    if s.y {
-        keys.Value |= cfg.controller_mapping.y.bit_pattern();
+
+    if poll_mode(cfg) == PollMode::Direct {
+        read_direct(cfg);
     }
-    if substick_x < 0 {
-        keys.Value |= cfg.controller_mapping.c_stick_left.bit_pattern();
+
+    let mut s = CONTROLLER_STATES[channel as usize].load();
+    if !s.connected {
+        if channel as usize + 1 == cfg.keyboard_port {
+            let max = cfg.control_stick_max;
+            let (stick_x, stick_y) = KEYBOARD.stick();
+            let mut buttons = N64Buttons::from_bits(KEYBOARD.buttons());
+            buttons.set_x_axis(stick_x.clamp(-max, max));
+            buttons.set_y_axis(stick_y.clamp(-max, max));
+            *keys = buttons.into();
+        }
+        return;
     }
-    if substick_x > 0 {
-        keys.Value |= cfg.controller_mapping.c_stick_right.bit_pattern();
+
+    if !cfg.reset_chord.is_empty()
+        && lock(&CHANNEL_STATES)[channel as usize].reset.update(
+            &cfg.reset_chord,
+            cfg.reset_chord_frames,
+            &s,
+        )
+    {
+        reset_rom();
     }
-    if substick_y < 0 {
-        keys.Value |= cfg.controller_mapping.c_stick_down.bit_pattern();
+
+    if cfg.socd_mode != SocdMode::Raw {
+        s = lock(&CHANNEL_STATES)[channel as usize]
+            .socd
+            .apply(s, cfg.socd_mode);
     }
-    if substick_y > 0 {
-        keys.Value |= cfg.controller_mapping.c_stick_up.bit_pattern();
+
+    let macro_buttons = if cfg.macros.is_empty() {
+        0
+    } else {
+        lock(&CHANNEL_STATES)[channel as usize]
+            .macros
+            .apply(&cfg.macros, &mut s)
+    };
+
+    let shift_buttons = if cfg.shift_button.is_some() {
+        lock(&CHANNEL_STATES)[channel as usize]
+            .shift
+            .apply(cfg, &mut s)
+    } else {
+        0
+    };
+
+    let profile = cfg
+        .profile(ACTIVE_PROFILES[channel as usize].load(Ordering::Acquire))
+        .map(|(_, p)| p);
+    let mut buttons = {
+        let mut channel_states = lock(&CHANNEL_STATES);
+        let ChannelState {
+            c_stick, triggers, ..
+        } = &mut channel_states[channel as usize];
+        mapping::map_state(&s, cfg, channel, profile, c_stick, triggers)
+    };
+    buttons.press_bits(shift_buttons);
+
+    let turbo_mask = cfg.turbo_mask();
+    if turbo_mask != 0 {
+        buttons = N64Buttons::from_bits(lock(&CHANNEL_STATES)[channel as usize].turbo.apply(
+            buttons.bits(),
+            turbo_mask,
+            cfg.turbo_rate,
+        ));
     }
-    if s.l || s.trigger_left > cfg.trigger_threshold {
-        keys.Value |= cfg.controller_mapping.l.bit_pattern();
+
+    if cfg.d_pad_repeat_delay != 0 {
+        buttons =
+            N64Buttons::from_bits(lock(&CHANNEL_STATES)[channel as usize].d_pad_repeat.apply(
+                buttons.bits(),
+                cfg.d_pad_repeat_delay,
+                cfg.d_pad_repeat_rate,
+            ));
     }
-    if s.r || s.trigger_right > cfg.trigger_threshold {
-        keys.Value |= cfg.controller_mapping.r.bit_pattern();
+
+    // Macros are added after turbo, so that their timing is kept
+    buttons.press_bits(macro_buttons);
+
+    if cfg.log_button_counts {
+        let counts = &mut lock(&CHANNEL_STATES)[channel as usize].button_counts;
+        for (bit, count) in counts.iter_mut().enumerate() {
+            if buttons.buttons() & (1 << bit) != 0 {
+                *count = count.saturating_add(1);
+            }
+        }
     }
-    if s.z {
-        keys.Value |= cfg.controller_mapping.z.bit_pattern();
+
+    *keys = buttons.into();
+}
+
+/// Soft reset the running ROM, for the reset chord.
+fn reset_rom() {
+    debug_print!(M64Message::Info, "Reset chord held, resetting the ROM");
+
+    match CoreApi::get().map(|api| api.reset(false)) {
+        Some(Ok(())) => {}
+        Some(Err(e)) => debug_print!(M64Message::Warning, "Could not reset the ROM: {}", e),
+        None => debug_print!(M64Message::Warning, "Could not reset the ROM: no core"),
     }
+}
+
+/// Read from the adapters on the emulator thread with `PollMode::Direct`, unless they were just read for another
+/// port. This waits for the adapters to send new input, which they do every 8 ms (or faster when overclocked).
+fn read_direct(cfg: &Config) {
+    /// The emulator reads the ports right after each other, so that a single read can be used for all of them
+    const SHARED_READ: Duration = Duration::from_millis(2);
+    /// Long enough for the next input from an adapter that is not overclocked
+    const DIRECT_READ_TIMEOUT: Duration = Duration::from_millis(10);
 
-    keys.__bindgen_anon_1.set_X_AXIS(stick_x as i32);
-    keys.__bindgen_anon_1.set_Y_AXIS(stick_y as i32);
+    let mut adapters = lock(&ADAPTERS);
+    if adapters
+        .last_read
+        .is_some_and(|read| read.elapsed() < SHARED_READ)
+    {
+        return;
+    }
+    adapters.read(cfg, DIRECT_READ_TIMEOUT);
 }
 
-/// Process the command and possibly read the controller. Currently unused, since raw data is disabled.
+/// Process the command and possibly read the controller. Does nothing and never writes to `command`, since raw data
+/// is disabled and the core answers the commands itself.
 ///
 /// # Safety
 ///
@@ -309,63 +870,753 @@ pub unsafe extern "C" fn GetKeys(control: c_int, keys: *mut BUTTONS) {
 #[no_mangle]
 pub unsafe extern "C" fn ReadController(_control: c_int, _command: *mut u8) {}
 
-/// Currently unused, only needed to be a valid input plugin.
+/// Process a command sent to the controller. Only rumble pak writes are handled, anything else is ignored.
+///
+/// # Safety
+///
+/// `command` must be null or point to a command buffer, with the length given by the first two bytes.
 #[no_mangle]
-pub extern "C" fn ControllerCommand(_control: c_int, _command: *mut c_uchar) {}
+pub unsafe extern "C" fn ControllerCommand(control: c_int, command: *mut c_uchar) {
+    let channel = match Channel::try_from(control) {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+    if command.is_null() {
+        return;
+    }
 
-/// Currently unused, only needed to be a valid input plugin.
+    // The lengths can have error flags set, and only the bytes that are parsed are read, so that a bad length never
+    // reaches past the command buffer
+    let len = pif::command_len(*command, *command.add(1)).min(pif::PARSED_LEN);
+    let command = slice::from_raw_parts(command, len);
+
+    if let Some(rumble) = pif::rumble_state(command) {
+        // Wireless controllers have no rumble motor, and the adapter does not need to power one
+        let rumble = rumble && !CONTROLLER_STATES[channel as usize].load().wireless;
+        send_rumble(RumbleCommand::Set(channel, rumble));
+    }
+}
+
+/// Called when a ROM is opened. Releases all keyboard keys, and starts recording or playing back input if enabled.
 #[no_mangle]
 pub extern "C" fn RomOpen() -> c_int {
     debug_print!(M64Message::Info, "RomOpen called");
 
+    KEYBOARD.release_all();
+    if let Some(cfg) = config() {
+        *lock(&RECORDING) = start_recording(cfg);
+    }
+
     1
 }
 
-/// Currently unused, only needed to be a valid input plugin.
+/// Called when a ROM is closed. Stops rumble, releases all keyboard keys, and stops recording or playing back input.
 #[no_mangle]
 pub extern "C" fn RomClosed() {
     debug_print!(M64Message::Info, "RomClosed called");
+
+    // The game cannot turn rumble off anymore
+    stop_rumble();
+
+    // The key up events are not received while no ROM is running
+    KEYBOARD.release_all();
+
+    if config().is_some_and(|cfg| cfg.log_button_counts) {
+        log_button_counts();
+    }
+
+    let recording = std::mem::replace(&mut *lock(&RECORDING), Recording::Off);
+    if let Recording::Record(mut recorder) = recording {
+        match recorder.flush() {
+            Ok(_) => debug_print!(M64Message::Info, "Recording stopped"),
+            Err(e) => debug_print!(M64Message::Error, "Could not save recording: {}", e),
+        }
+    }
+}
+
+/// Log how often each N64 button was pressed on each port since the last time, and reset the counts.
+fn log_button_counts() {
+    for (port, state) in lock(&CHANNEL_STATES).iter_mut().enumerate() {
+        let counts = std::mem::take(&mut state.button_counts);
+        let pressed = counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(bit, count)| match N64Button::from_bit(bit as u32) {
+                Some(button) => format!("{:?} {}", button, count),
+                None => format!("bit {} {}", bit, count),
+            })
+            .collect::<Vec<_>>();
+
+        if !pressed.is_empty() {
+            debug_print!(
+                M64Message::Info,
+                "Port {} button counts: {}",
+                port + 1,
+                pressed.join(", ")
+            );
+        }
+    }
+}
+
+/// Turn rumble off for all channels. Does nothing if the rumble thread is not running, and the rumble thread does not
+/// write to the adapter if rumble is already off.
+fn stop_rumble() {
+    for channel in [Channel::One, Channel::Two, Channel::Three, Channel::Four] {
+        send_rumble(RumbleCommand::Set(channel, false));
+    }
+}
+
+/// Open the recording that is played back, or create the file that input is recorded to.
+fn start_recording(cfg: &Config) -> Recording {
+    if let Some(path) = &cfg.playback_file {
+        if cfg.record_file.is_some() {
+            debug_print!(
+                M64Message::Warning,
+                "Both record_file and playback_file are set, only playing back"
+            );
+        }
+
+        return match Playback::open(path) {
+            Ok(playback) => {
+                debug_print!(M64Message::Status, "Playing back {}", path.display());
+                Recording::Playback(playback)
+            }
+            Err(e) => {
+                debug_print!(
+                    M64Message::Error,
+                    "Could not open recording {}: {}",
+                    path.display(),
+                    e
+                );
+                Recording::Off
+            }
+        };
+    }
+
+    match &cfg.record_file {
+        Some(path) => match Recorder::create(path) {
+            Ok(recorder) => {
+                debug_print!(M64Message::Status, "Recording to {}", path.display());
+                Recording::Record(recorder)
+            }
+            Err(e) => {
+                debug_print!(
+                    M64Message::Error,
+                    "Could not create recording {}: {}",
+                    path.display(),
+                    e
+                );
+                Recording::Off
+            }
+        },
+        None => Recording::Off,
+    }
 }
 
-/// Currently unused, only needed to be a valid input plugin.
+/// Handle a key press. The calibration key starts calibrating the sticks of all controllers, profile keys switch the
+/// profile of their port, and mapped keys press buttons on the keyboard port.
 #[no_mangle]
-pub extern "C" fn SDL_KeyDown(_keymod: c_int, _keysym: c_int) {
-    debug_print!(M64Message::Info, "SDL_KeyDown called");
+pub extern "C" fn SDL_KeyDown(_keymod: c_int, keysym: c_int) {
+    debug_print!(M64Message::Verbose, "SDL_KeyDown called");
+
+    if let Some(cfg) = config() {
+        if cfg.calibrate_key == Some(keysym) {
+            CALIBRATE.store(true, Ordering::Release);
+        }
+        for i in 0..4 {
+            if cfg.port(i).profile_key == Some(keysym) {
+                switch_profile(cfg, i);
+            }
+        }
+        KEYBOARD.key_down(keyboard_mapping(cfg), keysym);
+    }
+}
+
+/// Get the keys of the keyboard port, which are the fallback keys while no adapter is connected if none are mapped.
+fn keyboard_mapping(cfg: &Config) -> &KeyboardMapping {
+    if cfg.keyboard_fallback
+        && !cfg.keyboard_mapping.any_mapped()
+        && ADAPTER_COUNT.load(Ordering::Acquire) == 0
+    {
+        &KeyboardMapping::FALLBACK
+    } else {
+        &cfg.keyboard_mapping
+    }
+}
+
+/// Switch the given port to the next profile, or to no profile after the last one.
+fn switch_profile(cfg: &Config, port: usize) {
+    let count = cfg.profiles.len() + 1;
+    let index = (ACTIVE_PROFILES[port].load(Ordering::Acquire) + 1) % count;
+    ACTIVE_PROFILES[port].store(index, Ordering::Release);
+
+    match cfg.profile(index) {
+        Some((name, _)) => debug_print!(M64Message::Status, "Port {}: profile {}", port + 1, name),
+        None => debug_print!(M64Message::Status, "Port {}: no profile", port + 1),
+    }
 }
 
-/// Currently unused, only needed to be a valid input plugin.
+/// Handle a key release.
 #[no_mangle]
-pub extern "C" fn SDL_KeyUp(_keymod: c_int, _keysym: c_int) {
-    debug_print!(M64Message::Info, "SDL_KeyUp called");
+pub extern "C" fn SDL_KeyUp(_keymod: c_int, keysym: c_int) {
+    debug_print!(M64Message::Verbose, "SDL_KeyUp called");
+
+    if let Some(cfg) = config() {
+        KEYBOARD.key_up(&cfg.keyboard_mapping, keysym);
+        // The adapter can be connected while a fallback key is held
+        if cfg.keyboard_fallback {
+            KEYBOARD.key_up(&KeyboardMapping::FALLBACK, keysym);
+        }
+    }
 }
 
-pub fn start_read_thread() {
-    thread::spawn(move || {
-        debug_print!(M64Message::Info, "Adapter thread started");
-        debug_print!(M64Message::Info, "Trying to connect to GameCube adapter...");
+/// Get the number of N64 ports that a controller is connected to, or 0 if the plugin is not started.
+///
+/// This is not part of the Mupen64Plus input plugin API, it is an extension for front-ends that want to show the
+/// connected controllers.
+#[no_mangle]
+pub extern "C" fn GCAGetConnectedCount() -> c_int {
+    if !is_init() {
+        return 0;
+    }
+
+    CONTROLLER_STATES
+        .iter()
+        .filter(|state| state.load().connected)
+        .count() as c_int
+}
+
+/// Tell the plugin whether the emulator window has focus. With `neutral_on_focus_loss`, the ports send neutral input
+/// while it does not, and the held keys are released when it loses focus, since their key up events may go to another
+/// window.
+///
+/// This is not part of the Mupen64Plus input plugin API, it is an extension for front-ends, since the core does not
+/// tell input plugins about focus.
+#[no_mangle]
+pub extern "C" fn GCASetFocus(focused: c_int) {
+    debug_print!(M64Message::Verbose, "GCASetFocus called");
+
+    let focused = focused != 0;
+    let was_focused = FOCUSED.swap(focused, Ordering::AcqRel);
+    if was_focused && !focused && config().is_some_and(|cfg| cfg.neutral_on_focus_loss) {
+        KEYBOARD.release_all();
+    }
+}
+
+/// Read the configuration file again and make it the active configuration, so that front-ends can apply changes to
+/// the mapping without restarting the emulator. Returns `M64ERR_FILES` if the file could not be read, in which case
+/// the previous configuration stays active.
+///
+/// This is not part of the Mupen64Plus input plugin API, it is an extension for front-ends. The configuration file is
+/// also reloaded when it changes, but only when the adapter thread next looks for new adapters.
+#[no_mangle]
+pub extern "C" fn GCAReloadConfig() -> m64p_error {
+    debug_print!(M64Message::Info, "GCAReloadConfig called");
+
+    match reload_config(true) {
+        Some(Ok(_)) => m64p_error_M64ERR_SUCCESS,
+        Some(Err(_)) => m64p_error_M64ERR_FILES,
+        None => m64p_error_M64ERR_NOT_INIT,
+    }
+}
+
+/// Reload the configuration if its file changed, or always if `force` is set. Returns `None` if the plugin is not
+/// started or the file did not change.
+fn reload_config(force: bool) -> Option<io::Result<&'static Config>> {
+    let mut watcher = lock(&CONFIG_WATCHER);
+    let watcher = watcher.as_mut()?;
+    let result = if force {
+        watcher.reload()
+    } else {
+        watcher.reload_if_changed()?
+    };
+
+    match result {
+        Ok(new_cfg) => {
+            let old_cfg = config();
+            // Replaced configurations are leaked, so an unchanged one is not swapped in
+            if let Some(cfg) = old_cfg.filter(|cfg| **cfg == new_cfg) {
+                debug_print!(M64Message::Verbose, "Configuration did not change");
+                return Some(Ok(cfg));
+            }
+
+            debug_print!(M64Message::Status, "Reloaded configuration");
+            // Profiles that were switched to while the game runs are kept, unless they may have been renamed
+            let reset_profiles =
+                !old_cfg.is_some_and(|cfg| cfg.profiles.keys().eq(new_cfg.profiles.keys()));
+            let cfg = set_config(new_cfg, reset_profiles);
+            // Keys may be mapped differently now, so their release would be missed
+            KEYBOARD.release_all();
+            Some(Ok(cfg))
+        }
+        Err(e) => {
+            debug_print!(M64Message::Warning, "Could not reload config: {:?}", e);
+            Some(Err(e))
+        }
+    }
+}
+
+fn config() -> Option<&'static Config> {
+    // SAFETY: The pointer is either null or points to a leaked config, which is never freed
+    unsafe { CONFIG.load(Ordering::Acquire).as_ref() }
+}
+
+/// Make the given configuration the active one. If `reset_profiles` is set, the ports are switched to their initial
+/// profile.
+fn set_config(cfg: Config, reset_profiles: bool) -> &'static Config {
+    if let Some(level) = cfg.log_level {
+        debug::set_log_level(level);
+    }
+
+    if reset_profiles {
+        for (i, profile) in ACTIVE_PROFILES.iter().enumerate() {
+            profile.store(cfg.initial_profile(i), Ordering::Release);
+        }
+    }
+
+    let cfg = Box::leak(Box::new(cfg));
+    CONFIG.store(cfg, Ordering::Release);
+    cfg
+}
+
+fn is_init() -> bool {
+    IS_INIT.load(Ordering::Acquire)
+}
+
+/// Load the mock adapter from the script file in the `MUPEN64PLUS_INPUT_GCA_MOCK` environment variable, if it is set.
+/// See `MockAdapter::from_script` for the format.
+fn mock_adapter() -> Option<MockAdapter> {
+    let path = std::env::var_os("MUPEN64PLUS_INPUT_GCA_MOCK")?;
+    let mock = fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|script| MockAdapter::from_script(&script));
+
+    match mock {
+        Ok(mock) => {
+            debug_print!(
+                M64Message::Info,
+                "Using mock adapter from {}",
+                Path::new(&path).display()
+            );
+            Some(mock)
+        }
+        Err(e) => {
+            debug_print!(
+                M64Message::Warning,
+                "Could not load mock adapter from {}: {}",
+                Path::new(&path).display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Reads from the adapters, looks for newly connected adapters and reloads the configuration when its file changes.
+/// Runs on the adapter thread, or in `GetKeys` with the `sync-read` feature.
+struct ReadLoop {
+    cfg: &'static Config,
+    /// The stick calibrations that are saved between runs.
+    calibrations: CalibrationFile,
+    /// If set, only the mock adapter is used.
+    mock: Option<MockAdapter>,
+    use_mock: bool,
+    next_scan: Instant,
+    scans: u32,
+    next_diagnostics: Instant,
+    connected: [bool; 4],
+    status: ConnectionStatus,
+    backoff: ReconnectBackoff,
+    /// The rumble routes that were last sent to the rumble thread.
+    rumble_routes: Vec<[u8; 4]>,
+}
+
+impl ReadLoop {
+    /// How often to look for newly connected adapters
+    const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+    /// Adapters can take a moment to enumerate after the emulator starts, so the first scans are done more often
+    const STARTUP_SCANS: u32 = 5;
+    const STARTUP_SCAN_INTERVAL: Duration = Duration::from_millis(200);
+    /// How long to wait for input, so that new adapters and shutdown are still handled when an adapter is silent
+    const READ_TIMEOUT: Duration = Duration::from_millis(16);
+    /// How often the controller state is logged with `diagnostics_port`
+    const DIAGNOSTICS_INTERVAL: Duration = Duration::from_millis(250);
+
+    fn new(cfg: &'static Config, calibrations: CalibrationFile, mock: Option<MockAdapter>) -> Self {
+        ReadLoop {
+            cfg,
+            calibrations,
+            use_mock: mock.is_some(),
+            mock,
+            next_scan: Instant::now(),
+            scans: 0,
+            next_diagnostics: Instant::now(),
+            connected: [false; 4],
+            status: ConnectionStatus::new(Instant::now()),
+            backoff: ReconnectBackoff::new(),
+            rumble_routes: Vec::new(),
+        }
+    }
+
+    /// Run a single iteration of the loop. Returns how long to wait before the next one, or `None` to run it right
+    /// away.
+    fn step(&mut self) -> Option<Duration> {
+        ADAPTER_THREAD_WATCHDOG.feed(Instant::now());
+        warn_recovered_poison();
+        // GCAReloadConfig can also replace the configuration
+        self.cfg = config().unwrap_or(self.cfg);
+        let mut added = false;
+        let mut adapters = lock(&ADAPTERS);
 
-        let mut gc_adapter = GcAdapter::blocking_connect();
+        if Instant::now() >= self.next_scan {
+            if let Some(Ok(cfg)) = reload_config(false) {
+                self.cfg = cfg;
+            }
 
-        debug_print!(M64Message::Info, "Found a GameCube adapter");
+            let new_adapters: Vec<Box<dyn AdapterSource>> = match self.mock.take() {
+                Some(mock) => vec![Box::new(mock)],
+                None if self.use_mock => Vec::new(),
+                None => GcAdapter::open_new(&adapters.sources, self.cfg.send_init_command)
+                    .into_iter()
+                    .map(|gc| Box::new(gc) as _)
+                    .collect(),
+            };
+            if !new_adapters.is_empty() {
+                debug_print!(
+                    M64Message::Info,
+                    "Found {} GameCube adapter(s)",
+                    new_adapters.len()
+                );
+                adapters.add(new_adapters, self.cfg, &self.calibrations);
+                added = true;
+            }
 
-        while IS_INIT.load(Ordering::Acquire) {
-            match gc_adapter.read() {
-                Ok(buf) => ADAPTER_STATE.lock().unwrap().buf = buf,
-                Err(rusb::Error::NoDevice) => {
+            self.scans = self.scans.saturating_add(1);
+            self.next_scan = if let Some(interval) = self
+                .backoff
+                .interval()
+                .filter(|_| adapters.sources.is_empty())
+            {
+                Instant::now() + interval
+            } else if adapters.sources.is_empty() && self.scans < Self::STARTUP_SCANS {
+                debug_print!(
+                    M64Message::Info,
+                    "No adapter found (attempt {}/{}), retrying",
+                    self.scans,
+                    Self::STARTUP_SCANS
+                );
+                Instant::now() + Self::STARTUP_SCAN_INTERVAL
+            } else {
+                if self.scans == Self::STARTUP_SCANS && adapters.sources.is_empty() {
                     debug_print!(
                         M64Message::Info,
-                        "Adapter disconnected, trying to reconnect..."
+                        "No adapter found, looking for adapters every {:?}",
+                        Self::SCAN_INTERVAL
                     );
-                    gc_adapter = GcAdapter::blocking_connect();
-                    debug_print!(M64Message::Info, "Adapter reconnected");
                 }
-                Err(e) => panic!("error while reading from adapter: {e:?}"),
+                Instant::now() + Self::SCAN_INTERVAL
+            };
+        }
+        let cfg = self.cfg;
+
+        if CALIBRATE.swap(false, Ordering::AcqRel) {
+            debug_print!(M64Message::Info, "Calibrating sticks");
+            adapters.states.iter_mut().for_each(AdapterState::calibrate);
+        }
+
+        // With direct reads, GetKeys reads from the adapters instead. New adapters are still read once, so that the
+        // connected controllers are known before the game starts.
+        if poll_mode(cfg) != PollMode::Direct || added {
+            adapters.read(cfg, Self::READ_TIMEOUT);
+        }
+        let changed = std::mem::take(&mut adapters.changed);
+
+        for (id, port, offsets) in std::mem::take(&mut adapters.calibrated) {
+            match self.calibrations.save(&id, port, offsets) {
+                Ok(_) => debug_print!(
+                    M64Message::Info,
+                    "Saved calibration of port {} of adapter {}",
+                    port + 1,
+                    id
+                ),
+                Err(e) => debug_print!(
+                    M64Message::Warning,
+                    "Could not save calibration to {}: {:?}",
+                    self.calibrations.path().display(),
+                    e
+                ),
+            }
+        }
+
+        let max_interval = cfg.max_reconnect_interval();
+        if std::mem::take(&mut adapters.lost) {
+            let was_max = self.backoff.interval() == Some(max_interval);
+            let interval = self.backoff.lost(max_interval);
+            self.next_scan = Instant::now() + interval;
+            // Only logged once, instead of on every reconnect
+            if interval == max_interval && !was_max {
+                debug_print!(
+                    M64Message::Warning,
+                    "Adapter keeps disconnecting, reconnecting every {:?}",
+                    interval
+                );
+            }
+        }
+        self.backoff
+            .update(!adapters.sources.is_empty(), Instant::now(), max_interval);
+
+        // Controllers can be moved between ports while a game runs, which the core cannot be told about
+        for (i, was_connected) in self.connected.iter_mut().enumerate() {
+            let state = CONTROLLER_STATES[i].load();
+            let is_connected = state.connected;
+            let kind = if state.wireless {
+                "Wireless controller"
+            } else {
+                "Controller"
+            };
+            if is_connected == *was_connected {
+                continue;
+            }
+            *was_connected = is_connected;
+
+            if !is_connected {
+                debug_print!(
+                    M64Message::Info,
+                    "Controller disconnected from port {}",
+                    i + 1
+                );
+            } else if PRESENT_PORTS.load(Ordering::Acquire) & (1 << i) != 0 {
+                debug_print!(M64Message::Info, "{} connected to port {}", kind, i + 1);
+            } else {
+                debug_print!(
+                    M64Message::Warning,
+                    "{} connected to port {}, which the game does not use until it is restarted \
+                    (set all_controllers_present to always use all ports)",
+                    kind,
+                    i + 1
+                );
+            }
+        }
+
+        if let Some(port) = cfg.diagnostics_port {
+            if Instant::now() >= self.next_diagnostics {
+                let s = CONTROLLER_STATES[port - 1].load();
+                if s.connected {
+                    debug_print!(M64Message::Verbose, "Port {}: {}", port, s.summary());
+                } else {
+                    debug_print!(M64Message::Verbose, "Port {}: not connected", port);
+                }
+                self.next_diagnostics = Instant::now() + Self::DIAGNOSTICS_INTERVAL;
             }
+        }
+
+        match self
+            .status
+            .update(!adapters.sources.is_empty(), Instant::now())
+        {
+            Some(true) => debug_print!(M64Message::Status, "GC adapter connected"),
+            Some(false) => debug_print!(M64Message::Status, "GC adapter disconnected"),
+            None => {}
+        }
+
+        // The writers and routes are sent together, so that the rumble thread never routes to the wrong adapter
+        let routes = adapters.rumble_routes(cfg);
+        if changed || routes != self.rumble_routes {
+            let writers = adapters
+                .sources
+                .iter()
+                .zip(&routes)
+                .filter_map(|(source, &ports)| Some((source.rumble_writer()?, ports)))
+                .collect();
+            send_rumble(RumbleCommand::Adapters(writers));
+            self.rumble_routes = routes;
+        }
+
+        let no_adapters = adapters.sources.is_empty();
+        drop(adapters);
+
+        if no_adapters || poll_mode(cfg) == PollMode::Direct {
+            Some(self.next_scan.saturating_duration_since(Instant::now()))
+        } else if poll_mode(cfg) == PollMode::Sleep {
+            // The default of 1000 µs gives a polling rate of approx. 1000 Hz
+            Some(cfg.poll_interval())
+        } else {
+            None
+        }
+    }
+}
+
+/// Run an iteration of the read loop with the `sync-read` feature, returning how long to wait before the next one (see
+/// `ReadLoop::step`). Does nothing without it, or before `PluginStartup`.
+fn step_sync_read_loop() -> Option<Duration> {
+    lock(&SYNC_READ_LOOP).as_mut()?.step()
+}
+
+/// Wait at most `timeout` for the adapter thread to read the adapters, while `condition` holds.
+fn wait_for_read(
+    adapters: MutexGuard<'_, Adapters>,
+    timeout: Duration,
+    condition: impl FnMut(&mut Adapters) -> bool,
+) -> MutexGuard<'_, Adapters> {
+    ADAPTERS_READ
+        .wait_timeout_while(adapters, timeout, condition)
+        .unwrap_or_else(PoisonError::into_inner)
+        .0
+}
+
+/// Get how the adapters are read. This is always `PollMode::Direct` with the `sync-read` feature, since there is no
+/// adapter thread to read them.
+fn poll_mode(cfg: &Config) -> PollMode {
+    if cfg!(feature = "sync-read") {
+        PollMode::Direct
+    } else {
+        cfg.poll_mode
+    }
+}
+
+/// Start the thread that runs `read_loop`.
+fn start_read_thread(mut read_loop: ReadLoop) -> io::Result<JoinHandle<()>> {
+    /// How often the watchdog is fed while the thread waits, well within the timeout of `check_adapter_thread`
+    const WATCHDOG_FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+    spawn_named("adapter", move || {
+        debug_print!(M64Message::Info, "Adapter thread started");
+        debug_print!(M64Message::Info, "Trying to connect to GameCube adapter...");
 
-            // Gives a polling rate of approx. 1000 Hz
-            thread::park_timeout(Duration::from_millis(1));
+        while is_init() {
+            if let Some(wait) = read_loop.step() {
+                // The reconnect backoff can wait longer than the watchdog timeout, so the wait is split up. The thread
+                // is only unparked by PluginShutdown, which is noticed by checking is_init.
+                let until = Instant::now() + wait;
+                while is_init() && Instant::now() < until {
+                    let left = until.saturating_duration_since(Instant::now());
+                    thread::park_timeout(left.min(WATCHDOG_FEED_INTERVAL));
+                    ADAPTER_THREAD_WATCHDOG.feed(Instant::now());
+                }
+            }
         }
 
+        ADAPTER_THREAD_WATCHDOG.stop();
+        lock(&ADAPTERS).clear();
+
         debug_print!(M64Message::Info, "Adapter thread stopped");
-    });
+    })
+}
+
+/// The rumble of each channel, and the adapters that it is written to.
+struct Rumble {
+    /// The writer of each adapter, the channels that each of its ports rumbles for, and what was written to it
+    writers: Vec<(RumbleWriter, [u8; 4], Option<[bool; 4]>)>,
+    rumble: [bool; 4],
+}
+
+impl Rumble {
+    const fn new() -> Self {
+        Rumble {
+            writers: Vec::new(),
+            rumble: [false; 4],
+        }
+    }
+
+    fn handle(&mut self, cmd: RumbleCommand) {
+        match cmd {
+            RumbleCommand::Adapters(w) => {
+                // The routes may have changed, so the rumble of each adapter is written again
+                self.writers = w.into_iter().map(|(w, ports)| (w, ports, None)).collect();
+            }
+            RumbleCommand::Set(channel, on) => self.rumble[channel as usize] = on,
+        }
+    }
+
+    /// Write an output report to each adapter whose rumble changed.
+    fn write(&mut self) {
+        let mask = (0..4)
+            .filter(|&i| self.rumble[i])
+            .fold(0, |mask, i| mask | 1 << i);
+        for (w, ports, written) in &mut self.writers {
+            let adapter_rumble = ports.map(|channels| channels & mask != 0);
+            if *written == Some(adapter_rumble) {
+                continue;
+            }
+            match w.set_rumble(adapter_rumble) {
+                Ok(_) => *written = Some(adapter_rumble),
+                Err(e) => debug_print!(M64Message::Warning, "Could not set rumble: {:?}", e),
+            }
+        }
+    }
+}
+
+/// Send a command to the rumble thread, or handle it right away with the `sync-read` feature. Does nothing if the
+/// plugin is not started.
+fn send_rumble(cmd: RumbleCommand) {
+    if cfg!(feature = "sync-read") {
+        let mut rumble = lock(&SYNC_RUMBLE);
+        rumble.handle(cmd);
+        rumble.write();
+    } else if let Some(tx) = &*lock(&RUMBLE_SENDER) {
+        let _ = tx.send(cmd);
+    }
+}
+
+/// Start the thread that writes rumble output reports, so that USB writes never block the emulator or the adapter
+/// thread.
+fn start_rumble_thread(rumble_rx: Receiver<RumbleCommand>) -> io::Result<JoinHandle<()>> {
+    spawn_named("rumble", move || {
+        debug_print!(M64Message::Info, "Rumble thread started");
+
+        let mut rumble = Rumble::new();
+        while let Ok(cmd) = rumble_rx.recv() {
+            // Batch all pending commands into a single output report per adapter
+            for cmd in iter::once(cmd).chain(rumble_rx.try_iter()) {
+                rumble.handle(cmd);
+            }
+            rumble.write();
+        }
+
+        debug_print!(M64Message::Info, "Rumble thread stopped");
+    })
+}
+
+fn spawn_named<F: FnOnce() + Send + 'static>(name: &str, f: F) -> io::Result<JoinHandle<()>> {
+    thread::Builder::new().name(format!("gca-{name}")).spawn(f)
+}
+
+/// Lock the mutex, also if another thread panicked while holding it. The state behind the mutexes is always valid,
+/// so this avoids panicking in the FFI functions. The poison is cleared, so that each panic is only reported once by
+/// `warn_recovered_poison`.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| {
+        mutex.clear_poison();
+        RECOVERED_POISON.store(true, Ordering::Release);
+        e.into_inner()
+    })
+}
+
+/// Log a warning if the adapter thread has not gone through its loop for a while, e.g. because a USB read never
+/// returns, which stops the input from updating.
+fn check_adapter_thread() {
+    /// Longer than the adapter thread goes without feeding the watchdog, also while it waits to reconnect
+    const STALL_TIMEOUT: Duration = Duration::from_secs(3);
+
+    match ADAPTER_THREAD_WATCHDOG.check(Instant::now(), STALL_TIMEOUT) {
+        Some(true) => debug_print!(
+            M64Message::Warning,
+            "Adapter thread has not responded for {:?}, input is not updated",
+            STALL_TIMEOUT
+        ),
+        Some(false) => debug_print!(M64Message::Info, "Adapter thread is responding again"),
+        None => {}
+    }
+}
+
+/// Log a warning if `lock` recovered a poisoned mutex. This is not done in `lock` itself, because logging locks a
+/// mutex too.
+fn warn_recovered_poison() {
+    if RECOVERED_POISON.swap(false, Ordering::AcqRel) {
+        debug_print!(
+            M64Message::Warning,
+            "Recovered from a panic in another thread, input may have been interrupted"
+        );
+    }
 }
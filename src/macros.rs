@@ -0,0 +1,93 @@
+//! Macros, which press a timed sequence of N64 buttons while a combination of GC buttons is held.
+
+use crate::{adapter::ControllerState, config::Macro};
+
+/// Macro state for a single channel.
+#[derive(Debug)]
+pub struct Macros {
+    /// For how many frames each macro has been running, if its trigger is held.
+    running_frames: Vec<Option<u64>>,
+}
+
+impl Macros {
+    pub const fn new() -> Self {
+        Macros {
+            running_frames: Vec::new(),
+        }
+    }
+
+    /// Start the macros whose trigger is pressed, and cancel the ones whose trigger is released. The trigger buttons
+    /// of running macros are released in `state`, so that they do not press their mapped buttons too. Returns the
+    /// bit pattern of the N64 buttons that the running macros press, combined when more than one is running. Must be
+    /// called once per frame, so that the timing follows the game instead of the clock.
+    pub fn apply(&mut self, macros: &[Macro], state: &mut ControllerState) -> u32 {
+        // The configuration can be reloaded with a different number of macros
+        self.running_frames.resize(macros.len(), None);
+
+        let held = macros
+            .iter()
+            .map(|m| !m.trigger.is_empty() && m.trigger.iter().all(|&b| state.is_pressed(b)))
+            .collect::<Vec<_>>();
+        let mut buttons = 0;
+
+        for ((m, running_frames), held) in macros.iter().zip(&mut self.running_frames).zip(held) {
+            if !held {
+                *running_frames = None;
+                continue;
+            }
+
+            let frames = running_frames.map_or(0, |f| f.saturating_add(1));
+            *running_frames = Some(frames);
+            buttons |= m.buttons_at(frames);
+
+            for &button in &m.trigger {
+                state.release(button);
+            }
+        }
+
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GcButton, MacroStep, N64Button};
+
+    #[test]
+    fn runs_macro_while_held() {
+        let macros = [Macro {
+            trigger: vec![GcButton::X, GcButton::Y],
+            steps: vec![
+                MacroStep {
+                    buttons: vec![N64Button::A],
+                    frames: 2,
+                },
+                MacroStep {
+                    buttons: vec![N64Button::B, N64Button::Start],
+                    frames: 1,
+                },
+            ],
+        }];
+        let combo = ControllerState {
+            x: true,
+            y: true,
+            a: true,
+            ..Default::default()
+        };
+        let mut state = Macros::new();
+
+        let mut s = combo;
+        assert_eq!(state.apply(&macros, &mut s), 0x0080);
+        assert!(!s.x && !s.y && s.a);
+        assert_eq!(state.apply(&macros, &mut { combo }), 0x0080);
+        assert_eq!(state.apply(&macros, &mut { combo }), 0x0050);
+        assert_eq!(state.apply(&macros, &mut { combo }), 0);
+
+        // Releasing a trigger button cancels the macro, and pressing the combination again restarts it
+        let mut s = ControllerState { y: false, ..combo };
+        assert_eq!(state.apply(&macros, &mut s), 0);
+        assert!(s.x);
+        assert_eq!(state.apply(&macros, &mut { combo }), 0x0080);
+    }
+}
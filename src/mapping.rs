@@ -0,0 +1,821 @@
+//! Mapping of the GameCube controller state to the N64 controller.
+
+use crate::{
+    adapter::{Channel, ControllerState},
+    buttons::N64Buttons,
+    config::{
+        CStickMode, Config, GcButton, N64Button, Profile, StickAxis, StickGate, StickToDPad,
+        TriggerMode,
+    },
+    stick,
+};
+
+/// C-stick state that is kept between reads of a channel.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CStick {
+    /// The directions of the previous read, for the hysteresis of the thresholds.
+    directions: (i8, i8),
+    /// The deflection of each axis that was added up with `CStickMode::AnalogCamera`.
+    pulses: (f32, f32),
+}
+
+impl CStick {
+    /// The raw distance from the center where the C-button is pressed on every read with `CStickMode::AnalogCamera`.
+    const FULL_DEFLECTION: u8 = 100;
+
+    pub const fn new() -> Self {
+        CStick {
+            directions: (0, 0),
+            pulses: (0.0, 0.0),
+        }
+    }
+
+    /// Get the direction of the C-stick on each axis (-1, 0 or 1) for the given mode.
+    fn directions(&mut self, s: &ControllerState, cfg: &Config) -> (i8, i8) {
+        let (x, y) = cfg.c_stick_thresholds();
+        let previous = match cfg.c_stick_mode {
+            CStickMode::Digital => self.directions,
+            // The pulses already keep a C-button from toggling close to a threshold
+            CStickMode::AnalogCamera => (0, 0),
+        };
+        self.directions = s.substick_with_thresholds(x, y, cfg.c_stick_hysteresis, previous);
+
+        match cfg.c_stick_mode {
+            CStickMode::Digital => self.directions,
+            CStickMode::AnalogCamera => (
+                Self::pulse(s.substick_x, self.directions.0, x, &mut self.pulses.0),
+                Self::pulse(s.substick_y, self.directions.1, y, &mut self.pulses.1),
+            ),
+        }
+    }
+
+    /// Press the C-button of an axis on a share of the reads that grows with the distance past the threshold, by
+    /// adding up the deflection in `pulse` and pressing the button each time it reaches 1.
+    fn pulse(value: u8, direction: i8, (low, high): (u8, u8), pulse: &mut f32) -> i8 {
+        let (distance, range) = match direction {
+            -1 => (low - value, low.saturating_sub(128 - Self::FULL_DEFLECTION)),
+            1 => (
+                value - high,
+                (128 + Self::FULL_DEFLECTION).saturating_sub(high),
+            ),
+            _ => {
+                *pulse = 0.0;
+                return 0;
+            }
+        };
+
+        // Not reaching the threshold was handled above, so this is only 0 when the threshold is past the full
+        // deflection
+        *pulse += (distance as f32 / range.max(1) as f32).min(1.0);
+        if *pulse >= 1.0 {
+            *pulse -= 1.0;
+            direction
+        } else {
+            0
+        }
+    }
+}
+
+/// Trigger state that is kept between reads of a channel.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Triggers {
+    /// If the left and right trigger were past their threshold on the previous read, for the hysteresis.
+    pressed: (bool, bool),
+}
+
+impl Triggers {
+    pub const fn new() -> Self {
+        Triggers {
+            pressed: (false, false),
+        }
+    }
+
+    /// Check if each trigger is pressed past its threshold. A trigger is pressed once it is past the threshold plus
+    /// `hysteresis`, and released once it is back at the threshold minus `hysteresis`.
+    fn pressed(
+        &mut self,
+        s: &ControllerState,
+        (left, right): (u8, u8),
+        hysteresis: u8,
+    ) -> (bool, bool) {
+        let past = |value: u8, threshold: u8, pressed: bool| {
+            if pressed {
+                value > threshold.saturating_sub(hysteresis)
+            } else {
+                value > threshold.saturating_add(hysteresis)
+            }
+        };
+
+        self.pressed = (
+            past(s.trigger_left, left, self.pressed.0),
+            past(s.trigger_right, right, self.pressed.1),
+        );
+        self.pressed
+    }
+}
+
+/// Map the state of a GameCube controller to the N64 buttons and stick, using the settings of the given channel and
+/// its active profile. `c_stick` and `triggers` hold the state of the previous read of the channel, and are updated.
+pub fn map_state(
+    state: &ControllerState,
+    cfg: &Config,
+    channel: Channel,
+    profile: Option<&Profile>,
+    c_stick: &mut CStick,
+    triggers: &mut Triggers,
+) -> N64Buttons {
+    let mut s = *state;
+    let mut buttons = N64Buttons::new();
+    let deadzone = profile
+        .and_then(|p| p.control_stick_deadzone)
+        .unwrap_or_else(|| cfg.control_stick_deadzone(channel));
+
+    if cfg.swap_sticks {
+        s.swap_sticks();
+    }
+
+    let (left_range, right_range) = cfg.trigger_ranges();
+    s.trigger_left = stick::normalize_trigger(s.trigger_left, left_range);
+    s.trigger_right = stick::normalize_trigger(s.trigger_right, right_range);
+
+    let precision = cfg.precision_button.filter(|&b| s.is_pressed(b));
+    if let Some(button) = precision {
+        if cfg.precision_button_exclusive {
+            s.release(button);
+        }
+    }
+
+    let stick = s.stick_with_deadzone_shape(
+        deadzone,
+        cfg.control_stick_sensitivity,
+        cfg.control_stick_deadzone_shape,
+    );
+    let stick = stick::scale(stick, cfg.port(channel).control_stick_gain);
+    let stick_d_pad = match cfg.stick_to_d_pad {
+        StickToDPad::Off => (false, false, false, false),
+        StickToDPad::Add | StickToDPad::Replace => {
+            stick::to_directions(stick, cfg.stick_to_d_pad_threshold)
+        }
+    };
+    let stick = if cfg.stick_to_d_pad == StickToDPad::Replace {
+        (0, 0)
+    } else {
+        stick
+    };
+    let stick = stick::snap_directions(stick, cfg.control_stick_mode);
+    let stick = stick::anti_deadzone(stick, cfg.control_stick_anti_deadzone);
+    let stick = stick::response_curve(stick, cfg.control_stick_response_curve);
+    let stick = match cfg.control_stick_gate {
+        StickGate::None => stick,
+        StickGate::Octagon => stick::octagon_gate(stick, cfg.control_stick_gate_radius),
+    };
+    let stick = stick::scale(stick, cfg.control_stick_scale);
+    let stick = if precision.is_some() {
+        stick::scale(stick, cfg.precision_scale)
+    } else {
+        stick
+    };
+    let (mut stick_x, mut stick_y) = stick::invert(
+        stick,
+        cfg.control_stick_invert_x,
+        cfg.control_stick_invert_y,
+    );
+
+    // The D-pad overrides the control stick while it is held
+    if cfg.d_pad_drives_stick {
+        if s.left || s.right || s.down || s.up {
+            (stick_x, stick_y) = stick::from_directions(s.left, s.right, s.down, s.up);
+        }
+        s.left = false;
+        s.right = false;
+        s.down = false;
+        s.up = false;
+    }
+
+    // The trigger pressure is added to the chosen axis, next to the digital L and R buttons
+    if cfg.trigger_mode == TriggerMode::Analog {
+        let pressure = stick::trigger_axis(s.trigger_left, s.trigger_right, deadzone);
+        let add = |axis: i8| {
+            axis.saturating_add(pressure)
+                .clamp(-stick::N64_STICK_MAX, stick::N64_STICK_MAX)
+        };
+
+        match cfg.trigger_axis {
+            StickAxis::X => stick_x = add(stick_x),
+            StickAxis::Y => stick_y = add(stick_y),
+        }
+    }
+
+    let (substick_x, substick_y) = stick::invert(
+        c_stick.directions(&s, cfg),
+        cfg.c_stick_invert_x,
+        cfg.c_stick_invert_y,
+    );
+    let (trigger_left, trigger_right) =
+        triggers.pressed(&s, cfg.trigger_thresholds(), cfg.trigger_hysteresis);
+    let mapping = profile
+        .and_then(Profile::controller_mapping)
+        .unwrap_or_else(|| cfg.controller_mapping());
+
+    if s.right {
+        buttons.press(mapping.d_pad_right);
+    }
+    if s.left {
+        buttons.press(mapping.d_pad_left);
+    }
+    if s.down {
+        buttons.press(mapping.d_pad_down);
+    }
+    if s.up {
+        buttons.press(mapping.d_pad_up);
+    }
+    let (left, right, down, up) = stick_d_pad;
+    for (held, button) in [
+        (left, N64Button::DPadLeft),
+        (right, N64Button::DPadRight),
+        (down, N64Button::DPadDown),
+        (up, N64Button::DPadUp),
+    ] {
+        if held {
+            buttons.press(button);
+        }
+    }
+    if s.start {
+        buttons.press(mapping.start);
+    }
+    if s.a {
+        buttons.press(mapping.a);
+    }
+    if s.b {
+        buttons.press(mapping.b);
+    }
+    if s.x {
+        buttons.press(mapping.x);
+    }
+    if s.y {
+        buttons.press(mapping.y);
+    }
+    if substick_x < 0 {
+        buttons.press(mapping.c_stick_left);
+    }
+    if substick_x > 0 {
+        buttons.press(mapping.c_stick_right);
+    }
+    if substick_y < 0 {
+        buttons.press(mapping.c_stick_down);
+    }
+    if substick_y > 0 {
+        buttons.press(mapping.c_stick_up);
+    }
+    let both_triggers = cfg
+        .both_triggers_button
+        .filter(|_| trigger_left && trigger_right);
+    if let Some(button) = both_triggers {
+        buttons.press(button);
+    }
+    if !(both_triggers.is_some() && cfg.both_triggers_exclusive) {
+        // The digital click can be mapped separately from pressing the trigger past its threshold
+        if s.l {
+            buttons.press(mapping.l);
+        }
+        if trigger_left {
+            buttons.press(mapping.l_analog.unwrap_or(mapping.l));
+        }
+        if s.r {
+            buttons.press(mapping.r);
+        }
+        if trigger_right {
+            buttons.press(mapping.r_analog.unwrap_or(mapping.r));
+        }
+    }
+    if s.z {
+        buttons.press(mapping.z);
+    }
+    for button in GcButton::ALL.into_iter().filter(|&b| s.is_pressed(b)) {
+        for &extra in cfg.extra_buttons.get(button) {
+            buttons.press(extra);
+        }
+    }
+
+    // Every step above stays in the N64 range, but the configured range can be smaller
+    let max = cfg.control_stick_max;
+    let (stick_x, stick_y) = if cfg.diagnostics_raw_stick {
+        (
+            (state.stick_x as i16 - 128) as i8,
+            (state.stick_y as i16 - 128) as i8,
+        )
+    } else {
+        (stick_x.clamp(-max, max), stick_y.clamp(-max, max))
+    };
+
+    buttons.set_x_axis(stick_x);
+    buttons.set_y_axis(stick_y);
+    buttons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ButtonPreset, ControllerMapping, ExtraButtons, N64Button};
+
+    /// A connected controller with the sticks at the center.
+    fn centered() -> ControllerState {
+        ControllerState {
+            connected: true,
+            stick_x: 128,
+            stick_y: 128,
+            substick_x: 128,
+            substick_y: 128,
+            ..Default::default()
+        }
+    }
+
+    /// Map the state with the default config, returning the buttons and the stick position.
+    fn map(state: ControllerState) -> (u32, (i8, i8)) {
+        let keys = map_with(&Config::default(), &state);
+        (keys.buttons(), (keys.x_axis(), keys.y_axis()))
+    }
+
+    /// Map the state with the given config for the first channel, without a profile or a previous read.
+    fn map_with(cfg: &Config, state: &ControllerState) -> N64Buttons {
+        map_state(
+            state,
+            cfg,
+            Channel::One,
+            None,
+            &mut CStick::new(),
+            &mut Triggers::new(),
+        )
+    }
+
+    #[test]
+    fn maps_buttons() {
+        let cases = [
+            ("nothing", centered(), 0x0000),
+            (
+                "A",
+                ControllerState {
+                    a: true,
+                    ..centered()
+                },
+                0x0080,
+            ),
+            (
+                "B",
+                ControllerState {
+                    b: true,
+                    ..centered()
+                },
+                0x0040,
+            ),
+            (
+                "X is C-right",
+                ControllerState {
+                    x: true,
+                    ..centered()
+                },
+                0x0100,
+            ),
+            (
+                "Y is C-left",
+                ControllerState {
+                    y: true,
+                    ..centered()
+                },
+                0x0200,
+            ),
+            (
+                "Z is L",
+                ControllerState {
+                    z: true,
+                    ..centered()
+                },
+                0x2000,
+            ),
+            (
+                "L is Z",
+                ControllerState {
+                    l: true,
+                    ..centered()
+                },
+                0x0020,
+            ),
+            (
+                "R",
+                ControllerState {
+                    r: true,
+                    ..centered()
+                },
+                0x1000,
+            ),
+            (
+                "start",
+                ControllerState {
+                    start: true,
+                    ..centered()
+                },
+                0x0010,
+            ),
+            (
+                "D-pad up",
+                ControllerState {
+                    up: true,
+                    ..centered()
+                },
+                0x0008,
+            ),
+            (
+                "L trigger past threshold",
+                ControllerState {
+                    trigger_left: 200,
+                    ..centered()
+                },
+                0x0020,
+            ),
+            (
+                "L trigger below threshold",
+                ControllerState {
+                    trigger_left: 100,
+                    ..centered()
+                },
+                0x0000,
+            ),
+            (
+                "C-stick left",
+                ControllerState {
+                    substick_x: 20,
+                    ..centered()
+                },
+                0x0200,
+            ),
+            (
+                "C-stick up and Y",
+                ControllerState {
+                    substick_y: 230,
+                    y: true,
+                    ..centered()
+                },
+                0x0A00,
+            ),
+        ];
+
+        for (name, state, expected) in cases {
+            assert_eq!(map(state).0, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn maps_button_presets() {
+        let buttons = |preset, state: ControllerState| {
+            let cfg = Config {
+                button_preset: preset,
+                ..Default::default()
+            };
+            map_with(&cfg, &state).buttons()
+        };
+        let z = ControllerState {
+            z: true,
+            ..centered()
+        };
+        let l = ControllerState {
+            l: true,
+            ..centered()
+        };
+
+        assert_eq!(buttons(ButtonPreset::Classic, z), 0x2000);
+        assert_eq!(buttons(ButtonPreset::Classic, l), 0x0020);
+        assert_eq!(buttons(ButtonPreset::Natural, z), 0x0020);
+        assert_eq!(buttons(ButtonPreset::Natural, l), 0x2000);
+        assert_eq!(buttons(ButtonPreset::Pause, z), 0x0010);
+        assert_eq!(buttons(ButtonPreset::Pause, l), 0x0020);
+    }
+
+    #[test]
+    fn maps_c_stick_directions() {
+        let cfg = Config {
+            controller_mapping: ControllerMapping {
+                c_stick_up: N64Button::A,
+                c_stick_left: N64Button::Start,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let buttons = |state: ControllerState| map_with(&cfg, &state).buttons();
+
+        let up = ControllerState {
+            substick_y: 230,
+            ..centered()
+        };
+        assert_eq!(buttons(up), 0x0080);
+        let up_left = ControllerState {
+            substick_x: 20,
+            ..up
+        };
+        assert_eq!(buttons(up_left), 0x0090);
+        let down = ControllerState {
+            substick_y: 20,
+            ..centered()
+        };
+        assert_eq!(buttons(down), 0x0400);
+    }
+
+    #[test]
+    fn maps_trigger_click_separately() {
+        let cfg = Config {
+            controller_mapping: ControllerMapping {
+                r_analog: Some(N64Button::B),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let buttons = |state: ControllerState| map_with(&cfg, &state).buttons();
+
+        let squeeze = ControllerState {
+            trigger_right: 200,
+            ..centered()
+        };
+        assert_eq!(buttons(squeeze), 0x0040);
+        let click = ControllerState {
+            r: true,
+            trigger_right: 255,
+            ..centered()
+        };
+        assert_eq!(buttons(click), 0x1040);
+
+        // Without r_analog, both press the button of r
+        let l = ControllerState {
+            trigger_left: 200,
+            ..centered()
+        };
+        assert_eq!(buttons(l), 0x0020);
+    }
+
+    #[test]
+    fn trigger_hysteresis() {
+        let cfg = Config {
+            trigger_hysteresis: 10,
+            ..Default::default()
+        };
+        let mut triggers = Triggers::new();
+        let mut r = |trigger_right| {
+            let state = ControllerState {
+                trigger_right,
+                ..centered()
+            };
+            let keys = map_state(
+                &state,
+                &cfg,
+                Channel::One,
+                None,
+                &mut CStick::new(),
+                &mut triggers,
+            );
+            keys.buttons()
+        };
+
+        // Pressed past 178, released at 158
+        assert_eq!(r(170), 0);
+        assert_eq!(r(179), 0x1000);
+        assert_eq!(r(165), 0x1000);
+        assert_eq!(r(159), 0x1000);
+        assert_eq!(r(158), 0);
+        assert_eq!(r(175), 0);
+    }
+
+    #[test]
+    fn maps_both_triggers() {
+        let mut cfg = Config {
+            both_triggers_button: Some(N64Button::Start),
+            ..Default::default()
+        };
+        let buttons = |cfg: &Config, state: ControllerState| map_with(cfg, &state).buttons();
+        let left = ControllerState {
+            trigger_left: 200,
+            ..centered()
+        };
+        let both = ControllerState {
+            trigger_right: 200,
+            r: true,
+            ..left
+        };
+
+        assert_eq!(buttons(&cfg, left), 0x0020);
+        assert_eq!(buttons(&cfg, both), 0x1030);
+
+        cfg.both_triggers_exclusive = true;
+        assert_eq!(buttons(&cfg, left), 0x0020);
+        assert_eq!(buttons(&cfg, both), 0x0010);
+    }
+
+    #[test]
+    fn maps_extra_buttons() {
+        let cfg = Config {
+            extra_buttons: ExtraButtons {
+                a: vec![N64Button::Start],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let state = ControllerState {
+            a: true,
+            ..centered()
+        };
+
+        let keys = map_with(&cfg, &state);
+        assert_eq!(keys.buttons(), 0x0090);
+    }
+
+    #[test]
+    fn maps_stick_to_d_pad() {
+        let map = |mode| {
+            let cfg = Config {
+                stick_to_d_pad: mode,
+                ..Default::default()
+            };
+            let state = ControllerState {
+                stick_x: 128 + 100,
+                ..centered()
+            };
+            let keys = map_with(&cfg, &state);
+            (keys.buttons(), keys.x_axis())
+        };
+
+        assert_eq!(map(StickToDPad::Off), (0x0000, 80));
+        assert_eq!(map(StickToDPad::Add), (0x0001, 80));
+        assert_eq!(map(StickToDPad::Replace), (0x0001, 0));
+    }
+
+    #[test]
+    fn maps_with_profile() {
+        let profile = Profile {
+            control_stick_deadzone: Some(100),
+            button_preset: Some(ButtonPreset::Natural),
+            ..Default::default()
+        };
+        let state = ControllerState {
+            l: true,
+            stick_x: 128 + 60,
+            ..centered()
+        };
+
+        let value = map_state(
+            &state,
+            &Config::default(),
+            Channel::One,
+            Some(&profile),
+            &mut CStick::new(),
+            &mut Triggers::new(),
+        )
+        .bits();
+        assert_eq!(value, 0x2000);
+    }
+
+    #[test]
+    fn maps_analog_triggers() {
+        let cfg = Config {
+            trigger_mode: TriggerMode::Analog,
+            ..Default::default()
+        };
+        let axes = |state: ControllerState| {
+            let keys = map_with(&cfg, &state);
+            (keys.x_axis(), keys.y_axis())
+        };
+
+        assert_eq!(axes(centered()), (0, 0));
+        assert_eq!(
+            axes(ControllerState {
+                trigger_right: 255,
+                ..centered()
+            }),
+            (80, 0)
+        );
+
+        let (x, _) = axes(ControllerState {
+            trigger_left: 160,
+            ..centered()
+        });
+        assert!(x < 0 && x > -80);
+    }
+
+    #[test]
+    fn maps_control_stick() {
+        // Inside of the deadzone
+        assert_eq!(
+            map(ControllerState {
+                stick_x: 140,
+                ..centered()
+            })
+            .1,
+            (0, 0)
+        );
+        assert_eq!(map(centered()).1, (0, 0));
+
+        let (x, y) = map(ControllerState {
+            stick_x: 255,
+            ..centered()
+        })
+        .1;
+        assert!(x > 0 && y == 0);
+        let (x, y) = map(ControllerState {
+            stick_y: 0,
+            ..centered()
+        })
+        .1;
+        assert!(x == 0 && y < 0);
+    }
+
+    #[test]
+    fn clamps_to_control_stick_max() {
+        let cfg = Config {
+            control_stick_max: 60,
+            control_stick_scale: 2.0,
+            ..Default::default()
+        };
+        let full = ControllerState {
+            stick_x: 255,
+            stick_y: 0,
+            ..centered()
+        };
+
+        let axes = |cfg: &Config| {
+            let keys = map_with(cfg, &full);
+            (keys.x_axis(), keys.y_axis())
+        };
+        assert_eq!(axes(&cfg), (60, -60));
+        assert_eq!(axes(&Config::default()), (80, -80));
+    }
+
+    #[test]
+    fn analog_camera_pulses_c_buttons() {
+        let cfg = Config {
+            c_stick_mode: CStickMode::AnalogCamera,
+            ..Default::default()
+        };
+        let presses = |substick_x| {
+            let state = ControllerState {
+                substick_x,
+                ..centered()
+            };
+            let mut c_stick = CStick::new();
+            (0..10)
+                .filter(|_| {
+                    let keys = map_state(
+                        &state,
+                        &cfg,
+                        Channel::One,
+                        None,
+                        &mut c_stick,
+                        &mut Triggers::new(),
+                    );
+                    keys.is_pressed(N64Button::CRight)
+                })
+                .count()
+        };
+
+        // The high threshold is 168, and the C-button is pressed on every read at 228
+        assert_eq!(presses(150), 0);
+        assert_eq!(presses(198), 5);
+        assert_eq!(presses(228), 10);
+        assert_eq!(presses(255), 10);
+    }
+
+    #[test]
+    fn passes_raw_stick_through() {
+        let cfg = Config {
+            diagnostics_raw_stick: true,
+            ..Default::default()
+        };
+        let state = ControllerState {
+            stick_x: 255,
+            stick_y: 130,
+            ..centered()
+        };
+        let keys = map_with(&cfg, &state);
+        assert_eq!((keys.x_axis(), keys.y_axis()), (127, 2));
+    }
+
+    #[test]
+    fn applies_port_gain() {
+        let mut cfg = Config::default();
+        cfg.port_1.control_stick_gain = 1.5;
+        let state = ControllerState {
+            stick_x: 168,
+            ..centered()
+        };
+        let stick_x = |channel| {
+            let keys = map_state(
+                &state,
+                &cfg,
+                channel,
+                None,
+                &mut CStick::new(),
+                &mut Triggers::new(),
+            );
+            keys.x_axis()
+        };
+
+        let normal = stick_x(Channel::Two);
+        assert!(normal > 0 && normal < 50);
+        assert_eq!(stick_x(Channel::One), (normal as f32 * 1.5).round() as i8);
+    }
+}
@@ -0,0 +1,105 @@
+//! Parsing of the PIF commands that the core sends to the controllers.
+//!
+//! A command buffer has the following layout:
+//!
+//! | Byte          | Meaning                                     |
+//! |---------------|---------------------------------------------|
+//! | 0             | Number of bytes to send (including command) |
+//! | 1             | Number of bytes to receive                  |
+//! | 2             | Command                                     |
+//! | 3..           | Command data, followed by the response      |
+//!
+//! The plugin only reads commands, and never writes a response: raw data is disabled, so the core emulates the paks
+//! and answers the commands itself.
+
+const PAK_WRITE: u8 = 0x03;
+
+/// The bytes of a pak write that are parsed: the command, the address and the first data byte.
+const PAK_WRITE_PARSED_LEN: u8 = 4;
+
+/// The bits of the lengths that the PIF uses as error flags, e.g. when no controller responded.
+const LEN_FLAGS: u8 = 0xC0;
+
+/// The bytes of a command buffer that `rumble_state` parses: the lengths and the parsed bytes of a pak write.
+pub const PARSED_LEN: usize = 2 + PAK_WRITE_PARSED_LEN as usize;
+
+/// Writes to this address range of the controller pak control the rumble motor.
+const RUMBLE_ADDRESS_START: u16 = 0xC000;
+const RUMBLE_ADDRESS_END: u16 = 0xD000;
+
+/// Get the length of a command buffer from its first two bytes, the number of bytes to send and receive, without their
+/// error flags.
+pub fn command_len(send_len: u8, receive_len: u8) -> usize {
+    2 + (send_len & !LEN_FLAGS) as usize + (receive_len & !LEN_FLAGS) as usize
+}
+
+/// Parse a write to the rumble pak, returning whether the rumble motor should be turned on.
+///
+/// Returns `None` if the command is not a rumble pak write, or if the number of bytes to send is too small to contain
+/// the address and data, so that the response bytes are never parsed as data.
+pub fn rumble_state(command: &[u8]) -> Option<bool> {
+    if let [send_len, _, PAK_WRITE, address_hi, address_lo, data, ..] = *command {
+        if send_len & !LEN_FLAGS < PAK_WRITE_PARSED_LEN {
+            return None;
+        }
+
+        // The lower 5 bits of the address is a CRC
+        let address = u16::from_be_bytes([address_hi, address_lo]) & !0x1F;
+
+        if (RUMBLE_ADDRESS_START..RUMBLE_ADDRESS_END).contains(&address) {
+            return Some(data != 0);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pak_write(address: u16, data: u8) -> Vec<u8> {
+        let mut command = vec![0x23, 0x01, PAK_WRITE];
+        command.extend_from_slice(&address.to_be_bytes());
+        command.extend_from_slice(&[data; 32]);
+        command.push(0);
+        command
+    }
+
+    #[test]
+    fn parses_rumble_writes() {
+        assert_eq!(rumble_state(&pak_write(0xC01B, 0x01)), Some(true));
+        assert_eq!(rumble_state(&pak_write(0xC01B, 0x00)), Some(false));
+    }
+
+    #[test]
+    fn ignores_other_commands() {
+        assert_eq!(rumble_state(&pak_write(0x8001, 0x80)), None);
+        assert_eq!(rumble_state(&[0x01, 0x03, 0x00]), None);
+        assert_eq!(rumble_state(&[0x01, 0x04, 0x01, 0, 0, 0, 0]), None);
+        assert_eq!(rumble_state(&[]), None);
+
+        // A pak read from the rumble address range is not a write
+        let mut read = pak_write(0xC01B, 0x01);
+        read[0..3].copy_from_slice(&[0x03, 0x21, 0x02]);
+        assert_eq!(rumble_state(&read), None);
+    }
+
+    #[test]
+    fn ignores_short_commands() {
+        let mut command = pak_write(0xC01B, 0x01);
+        command[0] = 0x03;
+        assert_eq!(rumble_state(&command), None);
+    }
+
+    #[test]
+    fn masks_length_flags() {
+        assert_eq!(command_len(0x23, 0x01), 0x26);
+        assert_eq!(command_len(0x23, 0x81), 0x26);
+        assert_eq!(command_len(0xFF, 0xFF), 2 + 0x3F + 0x3F);
+
+        let mut command = pak_write(0xC01B, 0x01);
+        command[1] = 0x81;
+        assert_eq!(rumble_state(&command[..PARSED_LEN]), Some(true));
+    }
+}
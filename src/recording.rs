@@ -0,0 +1,150 @@
+//! Recording of the input that is sent to the emulator, and playback of recordings.
+//!
+//! A recording is a text file with a line for each time the emulator read a port: the read number for that port
+//! (starting from 0), the port (1 to 4) and the `BUTTONS` value in hexadecimal, separated by commas. Lines starting
+//! with `#` are ignored.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+const HEADER: &str = "# mupen64plus-input-gca recording: read, port, buttons";
+
+/// What is done with the input of the ports.
+#[derive(Debug)]
+pub enum Recording {
+    Off,
+    Record(Recorder),
+    Playback(Playback),
+}
+
+/// Writes the input of each read to a file.
+#[derive(Debug)]
+pub struct Recorder {
+    writer: BufWriter<File>,
+    reads: [u64; 4],
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+
+        Ok(Recorder {
+            writer,
+            reads: [0; 4],
+        })
+    }
+
+    /// Record the value that was read from the given port (starting from 0).
+    pub fn record(&mut self, port: usize, value: u32) -> io::Result<()> {
+        writeln!(
+            self.writer,
+            "{},{},{:08X}",
+            self.reads[port],
+            port + 1,
+            value
+        )?;
+        self.reads[port] += 1;
+
+        Ok(())
+    }
+
+    /// Write the buffered lines to the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Replays the input of a recording.
+#[derive(Debug)]
+pub struct Playback {
+    values: [Vec<u32>; 4],
+    reads: [usize; 4],
+}
+
+impl Playback {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let invalid = |line: usize| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid recording on line {}", line + 1),
+            )
+        };
+        let mut values: [Vec<u32>; 4] = Default::default();
+
+        for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split(',').map(str::trim);
+            let (read, port, value) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(read), Some(port), Some(value)) => (read, port, value),
+                _ => return Err(invalid(i)),
+            };
+            let port = port
+                .parse::<usize>()
+                .ok()
+                .filter(|p| (1..=4).contains(p))
+                .ok_or_else(|| invalid(i))?;
+            let read = read.parse::<usize>().map_err(|_| invalid(i))?;
+            let value = u32::from_str_radix(value, 16).map_err(|_| invalid(i))?;
+
+            // Reads are numbered in order, so a gap means that lines are missing
+            let port_values = &mut values[port - 1];
+            if read != port_values.len() {
+                return Err(invalid(i));
+            }
+            port_values.push(value);
+        }
+
+        Ok(Playback {
+            values,
+            reads: [0; 4],
+        })
+    }
+
+    /// Get the value of the next read from the given port (starting from 0). Returns neutral input (no buttons and
+    /// the stick at the center) after the end of the recording.
+    pub fn next(&mut self, port: usize) -> u32 {
+        let value = self.values[port].get(self.reads[port]).copied();
+        self.reads[port] += 1;
+        value.unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_back_recording() {
+        let path = std::env::temp_dir().join(format!(
+            "mupen64plus-input-gca-recording-test-{}.csv",
+            std::process::id()
+        ));
+
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record(0, 0x0000_0080).unwrap();
+        recorder.record(2, 0x5000_0010).unwrap();
+        recorder.record(0, 0xB0D8_0000).unwrap();
+        recorder.flush().unwrap();
+
+        let mut playback = Playback::open(&path).unwrap();
+        assert_eq!(playback.next(0), 0x0000_0080);
+        assert_eq!(playback.next(2), 0x5000_0010);
+        assert_eq!(playback.next(0), 0xB0D8_0000);
+        assert_eq!(playback.next(0), 0);
+        assert_eq!(playback.next(1), 0);
+
+        std::fs::write(&path, "0,1,80\n2,1,80\n").unwrap();
+        assert!(Playback::open(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+//! Repeating D-pad presses while a direction is held, for scrolling through menus.
+
+use crate::config::N64Button;
+
+const D_PAD: [N64Button; 4] = [
+    N64Button::DPadLeft,
+    N64Button::DPadRight,
+    N64Button::DPadDown,
+    N64Button::DPadUp,
+];
+
+/// D-pad repeat state for a single channel.
+#[derive(Debug)]
+pub struct DPadRepeat {
+    /// For how many frames each direction of `D_PAD` has been held, if it is held.
+    held_frames: [Option<u32>; 4],
+}
+
+impl DPadRepeat {
+    pub const fn new() -> Self {
+        DPadRepeat {
+            held_frames: [None; 4],
+        }
+    }
+
+    /// Release the held D-pad directions for a frame every `rate` frames, once they are held for `delay` frames, so
+    /// that the game sees them pressed again. Other buttons and the stick are not changed. Must be called once per
+    /// frame, so that the timing follows the game instead of the clock.
+    pub fn apply(&mut self, mut buttons: u32, delay: u16, rate: u8) -> u32 {
+        for (button, held_frames) in D_PAD.into_iter().zip(&mut self.held_frames) {
+            let bit = button.bit_pattern();
+            if buttons & bit == 0 {
+                *held_frames = None;
+                continue;
+            }
+
+            let frames = held_frames.map_or(0, |f| f.saturating_add(1));
+            *held_frames = Some(frames);
+            let repeating = frames.checked_sub(delay as u32);
+            if repeating.is_some_and(|f| f % rate.max(1) as u32 == 0) {
+                buttons &= !bit;
+            }
+        }
+
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_held_directions() {
+        const UP: u32 = 0x0008;
+        const A: u32 = 0x0080;
+
+        let mut repeat = DPadRepeat::new();
+
+        let pressed: Vec<_> = (0..10).map(|_| repeat.apply(UP | A, 4, 3)).collect();
+        let up = UP | A;
+        assert_eq!(pressed, [up, up, up, up, A, up, up, A, up, up]);
+
+        // Releasing the direction restarts the delay
+        assert_eq!(repeat.apply(A, 4, 3), A);
+        let pressed: Vec<_> = (0..5).map(|_| repeat.apply(UP, 4, 3)).collect();
+        assert_eq!(pressed, [UP, UP, UP, UP, 0]);
+    }
+}
@@ -0,0 +1,79 @@
+//! The reset chord, a combination of GC buttons that resets the ROM when held.
+
+use crate::{adapter::ControllerState, config::GcButton};
+
+/// Reset chord state for a single channel.
+#[derive(Debug)]
+pub struct ResetChord {
+    /// For how many frames all buttons of the chord have been held, if they are held.
+    held_frames: Option<u32>,
+    /// Whether the reset was triggered since the chord was pressed.
+    triggered: bool,
+}
+
+impl ResetChord {
+    pub const fn new() -> Self {
+        ResetChord {
+            held_frames: None,
+            triggered: false,
+        }
+    }
+
+    /// Check if the ROM should be reset, which is once when all buttons of `chord` have been held for `frames`
+    /// frames. The chord has to be released before it can trigger again. Must be called once per frame, so that the
+    /// timing follows the game instead of the clock.
+    pub fn update(&mut self, chord: &[GcButton], frames: u32, state: &ControllerState) -> bool {
+        if chord.is_empty() || !chord.iter().all(|&b| state.is_pressed(b)) {
+            self.held_frames = None;
+            self.triggered = false;
+            return false;
+        }
+
+        let held_frames = self.held_frames.map_or(0, |f| f.saturating_add(1));
+        self.held_frames = Some(held_frames);
+        if self.triggered || held_frames < frames {
+            return false;
+        }
+
+        self.triggered = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triggers_once_after_holding() {
+        let chord = [GcButton::Start, GcButton::Z, GcButton::R];
+        let held = ControllerState {
+            start: true,
+            z: true,
+            r: true,
+            ..Default::default()
+        };
+        let start_only = ControllerState {
+            start: true,
+            ..Default::default()
+        };
+        let mut reset = ResetChord::new();
+        let hold = |reset: &mut ResetChord, frames| {
+            (0..frames)
+                .map(|_| reset.update(&chord, 10, &held))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(!reset.update(&chord, 10, &start_only));
+        assert_eq!(hold(&mut reset, 10), [false; 10]);
+        assert!(reset.update(&chord, 10, &held));
+        assert_eq!(hold(&mut reset, 10), [false; 10]);
+
+        // Letting go of a button starts over
+        assert!(!reset.update(&chord, 10, &start_only));
+        assert_eq!(hold(&mut reset, 10), [false; 10]);
+        assert!(reset.update(&chord, 10, &held));
+
+        assert!(!reset.update(&[], 0, &held));
+    }
+}
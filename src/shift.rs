@@ -0,0 +1,95 @@
+//! The shift layer, which switches GC buttons to alternate N64 buttons while the shift button is held.
+
+use crate::{
+    adapter::ControllerState,
+    config::{Config, GcButton},
+};
+
+/// Shift layer state for a single channel.
+#[derive(Debug)]
+pub struct Shift {
+    /// Whether each held button (in the order of `GcButton::ALL`) was pressed while the shift button was held.
+    shifted: [Option<bool>; GcButton::ALL.len()],
+}
+
+impl Shift {
+    pub const fn new() -> Self {
+        Shift {
+            shifted: [None; GcButton::ALL.len()],
+        }
+    }
+
+    /// Release the buttons in `state` that are on the shift layer, and the shift button unless it passes through.
+    /// Returns the bit pattern of the alternate N64 buttons of the released buttons.
+    ///
+    /// A button keeps the layer it was pressed on until it is released, so that pressing or releasing the shift
+    /// button while holding it does not press another N64 button.
+    pub fn apply(&mut self, cfg: &Config, state: &mut ControllerState) -> u32 {
+        let shift_button = match cfg.shift_button {
+            Some(b) => b,
+            None => return 0,
+        };
+        let shift_held = state.is_pressed(shift_button);
+        let mut buttons = 0;
+
+        for (button, shifted) in GcButton::ALL.into_iter().zip(&mut self.shifted) {
+            if button == shift_button || !state.is_pressed(button) {
+                *shifted = None;
+                continue;
+            }
+
+            if !*shifted.get_or_insert(shift_held) {
+                continue;
+            }
+            if let Some(alternate) = cfg.shift_mapping.get(button) {
+                buttons |= alternate.bit_pattern();
+                state.release(button);
+            }
+        }
+
+        if shift_held && !cfg.shift_button_passthrough {
+            state.release(shift_button);
+        }
+
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{N64Button, ShiftMapping};
+
+    #[test]
+    fn shifts_buttons_while_held() {
+        let cfg = Config {
+            shift_button: Some(GcButton::Z),
+            shift_mapping: ShiftMapping {
+                a: Some(N64Button::CDown),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut shift = Shift::new();
+        let mut apply = |a, z, b| {
+            let mut s = ControllerState {
+                a,
+                z,
+                b,
+                ..Default::default()
+            };
+            let buttons = shift.apply(&cfg, &mut s);
+            (buttons, s.a, s.z, s.b)
+        };
+
+        assert_eq!(apply(true, false, false), (0, true, false, false));
+        // A was pressed before the shift button, so it stays on its usual button
+        assert_eq!(apply(true, true, true), (0, true, false, true));
+        assert_eq!(apply(false, true, false), (0, false, false, false));
+        assert_eq!(apply(true, true, false), (0x0400, false, false, false));
+        // Releasing the shift button first does not press A
+        assert_eq!(apply(true, false, false), (0x0400, false, false, false));
+        assert_eq!(apply(false, false, false), (0, false, false, false));
+        assert_eq!(apply(true, false, false), (0, true, false, false));
+    }
+}
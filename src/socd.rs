@@ -0,0 +1,131 @@
+//! Resolution of opposing D-pad directions that are held at the same time (SOCD).
+
+use crate::{adapter::ControllerState, config::SocdMode};
+
+/// SOCD state for a single channel.
+#[derive(Debug)]
+pub struct Socd {
+    left_right: Pair,
+    down_up: Pair,
+}
+
+impl Socd {
+    pub const fn new() -> Self {
+        Socd {
+            left_right: Pair::new(),
+            down_up: Pair::new(),
+        }
+    }
+
+    /// Resolve the left/right and down/up pairs of the D-pad with the given mode.
+    pub fn apply(&mut self, mut state: ControllerState, mode: SocdMode) -> ControllerState {
+        (state.left, state.right) = self.left_right.resolve(state.left, state.right, mode);
+        (state.down, state.up) = self.down_up.resolve(state.down, state.up, mode);
+        state
+    }
+}
+
+/// Two opposing directions.
+#[derive(Debug)]
+struct Pair {
+    held: (bool, bool),
+    /// The direction that wins while both are held with `SocdMode::LastWins`, `true` for the first one.
+    winner: Option<bool>,
+}
+
+impl Pair {
+    const fn new() -> Self {
+        Pair {
+            held: (false, false),
+            winner: None,
+        }
+    }
+
+    fn resolve(&mut self, first: bool, second: bool, mode: SocdMode) -> (bool, bool) {
+        let (was_first, was_second) = std::mem::replace(&mut self.held, (first, second));
+
+        if !(first && second) {
+            self.winner = None;
+            return (first, second);
+        }
+
+        match mode {
+            SocdMode::Raw => (true, true),
+            SocdMode::Neutral => (false, false),
+            SocdMode::LastWins => {
+                match (!was_first, !was_second) {
+                    (true, false) => self.winner = Some(true),
+                    (false, true) => self.winner = Some(false),
+                    // Pressed at the same time, so neither was last
+                    (true, true) => self.winner = None,
+                    (false, false) => {}
+                }
+
+                match self.winner {
+                    Some(first) => (first, !first),
+                    None => (false, false),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d_pad(left: bool, right: bool) -> ControllerState {
+        ControllerState {
+            left,
+            right,
+            ..Default::default()
+        }
+    }
+
+    fn resolve(socd: &mut Socd, left: bool, right: bool, mode: SocdMode) -> (bool, bool) {
+        let s = socd.apply(d_pad(left, right), mode);
+        (s.left, s.right)
+    }
+
+    #[test]
+    fn resolves_opposing_directions() {
+        let mut socd = Socd::new();
+        assert_eq!(resolve(&mut socd, true, true, SocdMode::Raw), (true, true));
+        assert_eq!(
+            resolve(&mut socd, true, true, SocdMode::Neutral),
+            (false, false)
+        );
+        assert_eq!(
+            resolve(&mut socd, true, false, SocdMode::Neutral),
+            (true, false)
+        );
+
+        let mut socd = Socd::new();
+        assert_eq!(
+            resolve(&mut socd, true, false, SocdMode::LastWins),
+            (true, false)
+        );
+        assert_eq!(
+            resolve(&mut socd, true, true, SocdMode::LastWins),
+            (false, true)
+        );
+        assert_eq!(
+            resolve(&mut socd, true, true, SocdMode::LastWins),
+            (false, true)
+        );
+        assert_eq!(
+            resolve(&mut socd, false, true, SocdMode::LastWins),
+            (false, true)
+        );
+        assert_eq!(
+            resolve(&mut socd, true, true, SocdMode::LastWins),
+            (true, false)
+        );
+
+        let mut socd = Socd::new();
+        assert_eq!(
+            resolve(&mut socd, true, true, SocdMode::LastWins),
+            (false, false)
+        );
+    }
+}
@@ -0,0 +1,348 @@
+//! Processing of the control stick after the deadzone has been applied.
+
+use crate::config::StickMode;
+use std::time::{Duration, Instant};
+
+/// The maximum value of an N64 stick axis.
+pub const N64_STICK_MAX: i8 = 80;
+
+/// Move the stick at least `minimum` away from the center as soon as it leaves the deadzone, keeping the direction of
+/// the stick. The range from the center to the maximum N64 stick value is rescaled to the range from `minimum` to the
+/// maximum.
+pub fn anti_deadzone((x, y): (i8, i8), minimum: f32) -> (i8, i8) {
+    let max = N64_STICK_MAX as f32;
+    let (x, y) = (x as f32, y as f32);
+
+    let radius = (x.powi(2) + y.powi(2)).sqrt();
+    if radius == 0.0 || minimum <= 0.0 {
+        return (x as i8, y as i8);
+    }
+
+    let rescaled = minimum + radius.min(max) * (max - minimum) / max;
+    let factor = rescaled / radius;
+
+    let axis = |v: f32| (v * factor).round().clamp(-max, max) as i8;
+    (axis(x), axis(y))
+}
+
+/// Apply a response curve to the distance of the stick from the center, keeping the direction of the stick.
+///
+/// The distance is normalized to the N64 stick range before raising it to the power of `exponent`, so the maximum
+/// N64 stick value is not changed by the curve.
+pub fn response_curve((x, y): (i8, i8), exponent: f32) -> (i8, i8) {
+    let max = N64_STICK_MAX as f32;
+    let (x, y) = (x as f32, y as f32);
+
+    let radius = (x.powi(2) + y.powi(2)).sqrt();
+    if radius == 0.0 {
+        return (0, 0);
+    }
+
+    // Distances past the N64 stick range are kept as they are, and will be clamped later
+    let normalized = radius / max;
+    let curved = if normalized < 1.0 {
+        normalized.powf(exponent)
+    } else {
+        normalized
+    };
+    let factor = curved / normalized;
+
+    ((x * factor).round() as i8, (y * factor).round() as i8)
+}
+
+/// Scale the stick by `scale`, clamping each axis to the N64 stick range.
+pub fn scale((x, y): (i8, i8), scale: f32) -> (i8, i8) {
+    let scale_axis = |v: i8| {
+        (v as f32 * scale)
+            .round()
+            .clamp(-N64_STICK_MAX as f32, N64_STICK_MAX as f32) as i8
+    };
+
+    (scale_axis(x), scale_axis(y))
+}
+
+/// Invert the X and/or Y axis of the stick.
+pub fn invert((x, y): (i8, i8), invert_x: bool, invert_y: bool) -> (i8, i8) {
+    let invert_axis = |v: i8, invert| if invert { v.saturating_neg() } else { v };
+
+    (invert_axis(x, invert_x), invert_axis(y, invert_y))
+}
+
+/// Limit the stick to a regular octagon with its corners on the axes and diagonals, like the notches of the N64 stick
+/// gate. The octagon has the given distance from the center to its corners, and is scaled so that its corners on the
+/// axes reach the maximum N64 stick value.
+pub fn octagon_gate((x, y): (i8, i8), radius: f32) -> (i8, i8) {
+    use std::f32::consts::{FRAC_PI_4, FRAC_PI_8};
+
+    let max = N64_STICK_MAX as f32;
+    let (x, y) = (x as f32, y as f32);
+
+    // The distance from the center to the edges of the octagon
+    let apothem = radius * FRAC_PI_8.cos();
+    // How far the stick is outside of the octagon, with 1.0 being on an edge
+    let outside = (0..8)
+        .map(|i| {
+            let angle = FRAC_PI_8 + i as f32 * FRAC_PI_4;
+            (x * angle.cos() + y * angle.sin()) / apothem
+        })
+        .fold(1.0, f32::max);
+
+    let factor = max / radius / outside;
+    let axis = |v: f32| (v * factor).round().clamp(-max, max) as i8;
+    (axis(x), axis(y))
+}
+
+/// Rescale a trigger value from the range that the trigger actually reaches (`[min, max]`) to the full range.
+pub fn normalize_trigger(value: u8, [min, max]: [u8; 2]) -> u8 {
+    if max <= min {
+        return value;
+    }
+
+    let travel = value.clamp(min, max) - min;
+    (travel as u32 * u8::MAX as u32 / (max - min) as u32) as u8
+}
+
+/// Get an N64 stick axis from the pressure of the triggers, with the left trigger moving the axis to the negative side
+/// and the right trigger to the positive side. The deadzone is in the same units as the control stick deadzone, where
+/// a fully pressed trigger is 127.
+pub fn trigger_axis(left: u8, right: u8, deadzone: u8) -> i8 {
+    let deadzone = deadzone.min(126) as f32;
+    let pressure = |trigger: u8| {
+        let distance = (trigger / 2) as f32 - deadzone;
+        (distance.max(0.0) * N64_STICK_MAX as f32 / (127.0 - deadzone)).round() as i8
+    };
+
+    pressure(right) - pressure(left)
+}
+
+/// Get the stick position for the held directions, fully deflected in each direction. Opposing directions cancel
+/// out, and diagonals are normalized to the same distance from the center as the axes.
+pub fn from_directions(left: bool, right: bool, down: bool, up: bool) -> (i8, i8) {
+    let axis = |negative: bool, positive: bool| positive as i8 - negative as i8;
+    let (x, y) = (axis(left, right), axis(down, up));
+
+    let max = if x != 0 && y != 0 {
+        (N64_STICK_MAX as f32 / std::f32::consts::SQRT_2).round() as i8
+    } else {
+        N64_STICK_MAX
+    };
+
+    (x * max, y * max)
+}
+
+/// Snap the stick to the nearest of the directions of the given mode, fully deflected. With `StickMode::EightWay` the
+/// diagonals are narrower than the axes, so that a stick that is held close to an axis does not flicker to a
+/// diagonal. The stick is not changed with `StickMode::Analog`, or when it is at the center.
+pub fn snap_directions((x, y): (i8, i8), mode: StickMode) -> (i8, i8) {
+    /// How far from a diagonal the stick can be to snap to it
+    const DIAGONAL_HALF_ANGLE: f32 = 15.0;
+
+    if mode == StickMode::Analog || (x, y) == (0, 0) {
+        return (x, y);
+    }
+
+    // The angle from the X axis within the quadrant of the stick, from 0 to 90 degrees
+    let angle = (y as f32).abs().atan2((x as f32).abs()).to_degrees();
+    let (horizontal, vertical) =
+        if mode == StickMode::EightWay && (angle - 45.0).abs() <= DIAGONAL_HALF_ANGLE {
+            (true, true)
+        } else {
+            (angle < 45.0, angle >= 45.0)
+        };
+
+    from_directions(
+        horizontal && x < 0,
+        horizontal && x > 0,
+        vertical && y < 0,
+        vertical && y > 0,
+    )
+}
+
+/// Get the D-pad directions (left, right, down and up) that the stick points to, with the diagonals of
+/// `StickMode::EightWay`. No direction is held while the stick is closer than `threshold` to the center.
+pub fn to_directions((x, y): (i8, i8), threshold: u8) -> (bool, bool, bool, bool) {
+    let distance = (x as f32).hypot(y as f32);
+    if distance == 0.0 || distance < threshold as f32 {
+        return (false, false, false, false);
+    }
+
+    let (x, y) = snap_directions((x, y), StickMode::EightWay);
+    (x < 0, x > 0, y < 0, y > 0)
+}
+
+/// An exponential moving average of the raw position of a stick, to reduce jitter from worn sticks.
+#[derive(Debug, Copy, Clone)]
+pub struct StickFilter {
+    /// The filtered position and when it was updated.
+    last: Option<((f32, f32), Instant)>,
+}
+
+impl StickFilter {
+    /// The interval of the adapter reports, which `smoothing` is relative to.
+    const REPORT_INTERVAL: Duration = Duration::from_millis(8);
+
+    pub const fn new() -> Self {
+        StickFilter { last: None }
+    }
+
+    /// Filter the raw stick position. `smoothing` is the part of the previous position that is kept every 8 ms, so
+    /// that the filter is the same for any polling rate. A `smoothing` of 0 turns the filter off.
+    pub fn apply(&mut self, (x, y): (u8, u8), smoothing: f32, now: Instant) -> (u8, u8) {
+        let (x, y) = (x as f32, y as f32);
+        let (x, y) = match self.last {
+            Some(((prev_x, prev_y), updated)) if smoothing > 0.0 => {
+                let elapsed = now.saturating_duration_since(updated);
+                let kept =
+                    smoothing.powf(elapsed.as_secs_f32() / Self::REPORT_INTERVAL.as_secs_f32());
+                (
+                    prev_x * kept + x * (1.0 - kept),
+                    prev_y * kept + y * (1.0 - kept),
+                )
+            }
+            _ => (x, y),
+        };
+
+        self.last = Some(((x, y), now));
+        (x.round() as u8, y.round() as u8)
+    }
+
+    /// Forget the previous position, e.g. when the controller is disconnected.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stick_filter_smooths_over_time() {
+        let start = Instant::now();
+        let interval = StickFilter::REPORT_INTERVAL;
+
+        let mut filter = StickFilter::new();
+        assert_eq!(filter.apply((128, 128), 0.5, start), (128, 128));
+        assert_eq!(filter.apply((228, 28), 0.5, start + interval), (178, 78));
+        // Two reads at twice the rate end up at the same position
+        let mut fast = StickFilter::new();
+        fast.apply((128, 128), 0.5, start);
+        fast.apply((228, 28), 0.5, start + interval / 2);
+        assert_eq!(fast.apply((228, 28), 0.5, start + interval), (178, 78));
+
+        assert_eq!(filter.apply((10, 20), 0.0, start + interval * 2), (10, 20));
+        filter.reset();
+        assert_eq!(
+            filter.apply((200, 20), 0.5, start + interval * 3),
+            (200, 20)
+        );
+    }
+
+    #[test]
+    fn response_curve_keeps_max_and_direction() {
+        assert_eq!(response_curve((40, 0), 1.0), (40, 0));
+        assert_eq!(response_curve((40, 0), 2.0), (20, 0));
+        assert_eq!(response_curve((-40, 0), 2.0), (-20, 0));
+        assert_eq!(response_curve((0, -80), 2.0), (0, -80));
+        assert_eq!(response_curve((0, 0), 2.0), (0, 0));
+
+        let (x, y) = response_curve((30, -30), 3.0);
+        assert_eq!(x, -y);
+        assert!(x > 0 && x < 30);
+    }
+
+    #[test]
+    fn anti_deadzone_rescales_range() {
+        assert_eq!(anti_deadzone((0, 0), 20.0), (0, 0));
+        assert_eq!(anti_deadzone((1, 0), 20.0), (21, 0));
+        assert_eq!(anti_deadzone((0, -40), 20.0), (0, -50));
+        assert_eq!(anti_deadzone((80, 0), 20.0), (80, 0));
+        assert_eq!(anti_deadzone((-90, 0), 20.0), (-80, 0));
+        assert_eq!(anti_deadzone((30, 40), 0.0), (30, 40));
+
+        let (x, y) = anti_deadzone((1, 1), 20.0);
+        assert_eq!(x, y);
+        assert!(x >= 14);
+    }
+
+    #[test]
+    fn invert_does_not_overflow() {
+        assert_eq!(invert((10, -10), true, false), (-10, -10));
+        assert_eq!(invert((10, -10), false, true), (10, 10));
+        assert_eq!(invert((-128, 0), true, true), (127, 0));
+    }
+
+    #[test]
+    fn scale_clamps_to_n64_range() {
+        assert_eq!(scale((40, -20), 1.0), (40, -20));
+        assert_eq!(scale((40, -20), 1.5), (60, -30));
+        assert_eq!(scale((70, -75), 1.5), (80, -80));
+        assert_eq!(scale((127, -128), 1.0), (80, -80));
+    }
+
+    #[test]
+    fn octagon_gate_limits_to_octagon() {
+        assert_eq!(octagon_gate((0, 0), 80.0), (0, 0));
+        assert_eq!(octagon_gate((30, -20), 80.0), (30, -20));
+        assert_eq!(octagon_gate((127, 0), 80.0), (80, 0));
+        assert_eq!(octagon_gate((0, -128), 80.0), (0, -80));
+        assert_eq!(octagon_gate((127, 127), 80.0), (57, 57));
+        assert_eq!(octagon_gate((70, 0), 70.0), (80, 0));
+
+        // Between the corners, the stick is limited to the edge of the octagon
+        let (x, y) = octagon_gate((80, 40), 80.0);
+        assert!(x < 80 && y < 40 && x > 2 * y - 2);
+    }
+
+    #[test]
+    fn snap_directions_to_mode() {
+        assert_eq!(snap_directions((30, 9), StickMode::Analog), (30, 9));
+        assert_eq!(snap_directions((0, 0), StickMode::EightWay), (0, 0));
+        assert_eq!(snap_directions((30, 9), StickMode::EightWay), (80, 0));
+        assert_eq!(snap_directions((-30, 30), StickMode::EightWay), (-57, 57));
+        assert_eq!(snap_directions((-30, 30), StickMode::FourWay), (0, 80));
+        // Close to an axis, but past where equal sectors would switch to the diagonal
+        assert_eq!(snap_directions((40, -20), StickMode::EightWay), (80, 0));
+        assert_eq!(snap_directions((-10, -40), StickMode::FourWay), (0, -80));
+    }
+
+    #[test]
+    fn to_directions_past_threshold() {
+        assert_eq!(to_directions((0, 0), 0), (false, false, false, false));
+        assert_eq!(to_directions((30, 9), 40), (false, false, false, false));
+        assert_eq!(to_directions((50, 9), 40), (false, true, false, false));
+        assert_eq!(to_directions((-40, 40), 40), (true, false, false, true));
+    }
+
+    #[test]
+    fn normalize_trigger_rescales_range() {
+        assert_eq!(normalize_trigger(100, [0, 255]), 100);
+        assert_eq!(normalize_trigger(20, [30, 230]), 0);
+        assert_eq!(normalize_trigger(30, [30, 230]), 0);
+        assert_eq!(normalize_trigger(130, [30, 230]), 127);
+        assert_eq!(normalize_trigger(230, [30, 230]), 255);
+        assert_eq!(normalize_trigger(250, [30, 230]), 255);
+    }
+
+    #[test]
+    fn trigger_axis_is_graduated() {
+        assert_eq!(trigger_axis(0, 0, 20), 0);
+        assert_eq!(trigger_axis(40, 0, 20), 0);
+        assert_eq!(trigger_axis(255, 0, 20), -80);
+        assert_eq!(trigger_axis(0, 255, 20), 80);
+        assert_eq!(trigger_axis(255, 255, 20), 0);
+        assert_eq!(trigger_axis(0, 128, 0), 40);
+
+        let half = trigger_axis(0, 150, 20);
+        assert!(half > 0 && half < 80);
+    }
+
+    #[test]
+    fn directions_cancel_and_normalize() {
+        assert_eq!(from_directions(false, false, false, false), (0, 0));
+        assert_eq!(from_directions(true, false, false, false), (-80, 0));
+        assert_eq!(from_directions(false, false, false, true), (0, 80));
+        assert_eq!(from_directions(true, true, false, true), (0, 80));
+        assert_eq!(from_directions(false, true, true, false), (57, -57));
+    }
+}
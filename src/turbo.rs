@@ -0,0 +1,71 @@
+//! Turbo (autofire) for N64 buttons.
+
+/// Turbo state for a single channel.
+#[derive(Debug)]
+pub struct Turbo {
+    /// For how many frames each button bit has been held, if it is held.
+    held_frames: [Option<u32>; 16],
+}
+
+impl Turbo {
+    pub const fn new() -> Self {
+        Turbo {
+            held_frames: [None; 16],
+        }
+    }
+
+    /// Toggle each held button in `mask` every `rate` frames, starting with the button pressed. Each button keeps its
+    /// own timing, so that pressing another turbo button does not change it. Must be called once per frame, so that
+    /// the timing follows the game instead of the clock.
+    pub fn apply(&mut self, mut buttons: u32, mask: u32, rate: u8) -> u32 {
+        for (i, held_frames) in self.held_frames.iter_mut().enumerate() {
+            let bit = 1 << i;
+            if buttons & mask & bit == 0 {
+                *held_frames = None;
+                continue;
+            }
+
+            let frames = held_frames.map_or(0, |f| f.saturating_add(1));
+            *held_frames = Some(frames);
+            if (frames / rate.max(1) as u32) % 2 != 0 {
+                buttons &= !bit;
+            }
+        }
+
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles_masked_buttons() {
+        const A: u32 = 0x0080;
+        const B: u32 = 0x0040;
+
+        let mut turbo = Turbo::new();
+
+        let pressed: Vec<_> = (0..5).map(|_| turbo.apply(A | B, A, 2)).collect();
+        assert_eq!(pressed, [A | B, A | B, B, B, A | B]);
+
+        // Releasing the button restarts the turbo
+        assert_eq!(turbo.apply(B, A, 2), B);
+        assert_eq!(turbo.apply(A, A, 2), A);
+    }
+
+    #[test]
+    fn buttons_keep_their_own_timing() {
+        const A: u32 = 0x0080;
+        const B: u32 = 0x0040;
+
+        let mut turbo = Turbo::new();
+
+        assert_eq!(turbo.apply(A, A | B, 1), A);
+        // B starts pressed, while A keeps toggling
+        assert_eq!(turbo.apply(A | B, A | B, 1), B);
+        assert_eq!(turbo.apply(A | B, A | B, 1), A);
+        assert_eq!(turbo.apply(A | B, A | B, 1), B);
+    }
+}
@@ -0,0 +1,86 @@
+//! A watchdog that notices when a thread stops making progress.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// Tracks when a thread last fed the watchdog, so that other threads can check if it stalled.
+#[derive(Debug)]
+pub struct Watchdog {
+    /// The time that `fed` is relative to, set when the watchdog is first fed.
+    epoch: OnceLock<Instant>,
+    /// When the watchdog was last fed, in milliseconds after `epoch` plus one, or 0 while it is stopped.
+    fed: AtomicU64,
+    stalled: AtomicBool,
+}
+
+impl Watchdog {
+    pub const fn new() -> Self {
+        Watchdog {
+            epoch: OnceLock::new(),
+            fed: AtomicU64::new(0),
+            stalled: AtomicBool::new(false),
+        }
+    }
+
+    /// Record that the thread is still making progress.
+    pub fn feed(&self, now: Instant) {
+        let epoch = *self.epoch.get_or_init(|| now);
+        let millis = now.saturating_duration_since(epoch).as_millis() as u64;
+        self.fed.store(millis + 1, Ordering::Release);
+    }
+
+    /// Stop checking the thread, e.g. because it stopped on purpose.
+    pub fn stop(&self) {
+        self.fed.store(0, Ordering::Release);
+        self.stalled.store(false, Ordering::Release);
+    }
+
+    /// Check if the thread was fed within `timeout`. Returns `Some(true)` the first time it is found to be stalled,
+    /// `Some(false)` when it makes progress again after that, and `None` otherwise.
+    pub fn check(&self, now: Instant, timeout: Duration) -> Option<bool> {
+        let fed = self.fed.load(Ordering::Acquire);
+        let epoch = match self.epoch.get() {
+            Some(epoch) if fed != 0 => *epoch,
+            _ => return None,
+        };
+
+        let since_fed = now
+            .saturating_duration_since(epoch)
+            .saturating_sub(Duration::from_millis(fed - 1));
+        let stalled = since_fed > timeout;
+
+        (self.stalled.swap(stalled, Ordering::AcqRel) != stalled).then_some(stalled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_stalls_once() {
+        let timeout = Duration::from_secs(3);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let watchdog = Watchdog::new();
+
+        assert_eq!(watchdog.check(at(10), timeout), None);
+
+        watchdog.feed(start);
+        assert_eq!(watchdog.check(at(2), timeout), None);
+        assert_eq!(watchdog.check(at(4), timeout), Some(true));
+        assert_eq!(watchdog.check(at(5), timeout), None);
+
+        watchdog.feed(at(6));
+        assert_eq!(watchdog.check(at(6), timeout), Some(false));
+        assert_eq!(watchdog.check(at(7), timeout), None);
+
+        watchdog.stop();
+        assert_eq!(watchdog.check(at(20), timeout), None);
+    }
+}